@@ -32,4 +32,5 @@ rm -rf %{buildroot}
 %defattr(-,root,root,-)
 /usr/lib/mozilla/native-messaging-hosts/ax.nd.profile_switcher_ff.json
 /usr/lib64/mozilla/native-messaging-hosts/ax.nd.profile_switcher_ff.json
+/usr/share/applications/ff-pswitch-connector.desktop
 %{_bindir}/*
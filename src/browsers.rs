@@ -0,0 +1,469 @@
+use std::{env, fs};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use cfg_if::cfg_if;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::Serialize;
+use crate::desktop_entries::LaunchTemplate;
+
+cfg_if! {
+    if #[cfg(target_os = "linux")] {
+        use crate::desktop_entries::{read_desktop_entries, resolve_program, DesktopEntry};
+    }
+}
+
+// === BROWSER DISCOVERY ===
+
+// Executable names of known Firefox-family browsers, in order of preference
+const BROWSER_EXECUTABLES: [&str; 11] = [
+    "firefox",
+    "firefox-esr",
+    "firefox-developer-edition",
+    "firefox-nightly",
+    "librewolf",
+    "waterfox",
+    "zen-browser",
+    "zen",
+    "floorp",
+    "mullvadbrowser",
+    "mullvad-browser"
+];
+
+// Names identifying Firefox-family browsers in executable names, window classes and Flatpak IDs
+#[cfg(target_os = "linux")]
+const BROWSER_FAMILY_NAMES: [&str; 7] = ["firefox", "librewolf", "waterfox", "zen", "floorp", "mullvadbrowser", "mullvad-browser"];
+
+static CHANNEL_PREF_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r#"pref\(\s*"app\.update\.channel"\s*,\s*"([^"]+)"\s*\)"#).unwrap()
+});
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BrowserChannel {
+    Release,
+    Beta,
+    DeveloperEdition,
+    Nightly,
+    Esr
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum InstallType {
+    // Installed by the package manager of the distribution
+    Distro,
+    Flatpak,
+    Snap,
+    // Extracted by hand, e.g. into /opt or ~/opt
+    Tarball,
+    // Installed by the installer of the vendor (macOS and Windows)
+    Installer
+}
+
+#[derive(Clone, Debug)]
+pub struct InstalledBrowser {
+    pub product_name: String,
+    pub version: Option<String>,
+    pub channel: BrowserChannel,
+    pub install_type: InstallType,
+    // The binary to launch the browser with, this may be a wrapper outside of the install dir
+    pub binary: PathBuf,
+    // The directory containing application.ini, unknown for some desktop entries
+    pub install_dir: Option<PathBuf>,
+    pub desktop_entry: Option<PathBuf>,
+    // How the desktop launches the browser, preferred over the binary
+    pub launch_template: Option<LaunchTemplate>
+}
+
+/// Find every Firefox-family browser installed on this machine, most preferred first.
+pub fn discover_browsers() -> Vec<InstalledBrowser> {
+    let mut browsers = Vec::new();
+
+    cfg_if! {
+        if #[cfg(target_os = "linux")] {
+            discover_flatpak_browsers(&mut browsers);
+            discover_distro_browsers(&mut browsers);
+            discover_snap_browsers(&mut browsers);
+            discover_tarball_browsers(&mut browsers);
+            discover_desktop_browsers(&mut browsers);
+        } else if #[cfg(target_os = "macos")] {
+            discover_macos_browsers(&mut browsers);
+        } else if #[cfg(target_os = "windows")] {
+            discover_windows_browsers(&mut browsers);
+        }
+    }
+
+    // The same installation may be reachable through multiple paths (e.g. /usr/lib64 -> /usr/lib)
+    let mut seen = HashSet::new();
+    browsers.retain(|b| {
+        let path = b.install_dir.as_ref().unwrap_or(&b.binary);
+        seen.insert(fs::canonicalize(path).unwrap_or_else(|_| path.clone()))
+    });
+
+    log::trace!("Discovered browsers: {:?}", browsers);
+    browsers
+}
+
+// Find the most preferred browser installed on this machine
+pub fn find_browser() -> Option<InstalledBrowser> {
+    let browser = discover_browsers().into_iter().next()?;
+    log::info!("Found browser binary at: {:?}", browser.binary);
+    Some(browser)
+}
+
+#[cfg(target_os = "linux")]
+fn flatpak_installations() -> Vec<PathBuf> {
+    let mut installations = Vec::new();
+    if let Some(base_dirs) = directories::BaseDirs::new() {
+        installations.push(base_dirs.data_local_dir().join("flatpak"));
+    }
+    installations.push(PathBuf::from("/var/lib/flatpak"));
+    installations
+}
+
+#[cfg(target_os = "linux")]
+fn discover_flatpak_browsers(browsers: &mut Vec<InstalledBrowser>) {
+    // Paths look like this: <installation>/app/<app id>/current/active/files/lib/firefox/application.ini
+    for app_dir in flatpak_installations().iter().flat_map(|i| list_dirs(&i.join("app"))) {
+        let files_dir = app_dir.join("current").join("active").join("files");
+        for install_dir in find_install_dirs(&files_dir, 2) {
+            if let Some(mut browser) = read_browser(&install_dir, &install_dir, InstallType::Flatpak) {
+                // Prefer the wrapper the Flatpak itself uses as its entry point
+                if let Some(wrapper) = browser.binary.file_name().map(|n| files_dir.join("bin").join(n)) {
+                    if wrapper.exists() {
+                        browser.binary = wrapper;
+                    }
+                }
+                browsers.push(browser);
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn discover_distro_browsers(browsers: &mut Vec<InstalledBrowser>) {
+    for lib_dir in ["/usr/lib", "/usr/lib64", "/usr/local/lib"] {
+        for install_dir in find_install_dirs(Path::new(lib_dir), 1) {
+            if let Some(mut browser) = read_browser(&install_dir, &install_dir, InstallType::Distro) {
+                // Distributions usually ship a wrapper script that sets up the environment of the browser
+                if let Some(name) = browser.binary.file_name() {
+                    let wrapper = ["/usr/bin", "/usr/local/bin"].iter()
+                        .map(|bin_dir| Path::new(bin_dir).join(name))
+                        .find(|p| p.exists());
+                    if let Some(wrapper) = wrapper {
+                        browser.binary = wrapper;
+                    }
+                }
+                browsers.push(browser);
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn discover_snap_browsers(browsers: &mut Vec<InstalledBrowser>) {
+    // Paths look like this: /snap/<name>/current/usr/lib/firefox/application.ini
+    for snap_dir in list_dirs(Path::new("/snap")) {
+        let snap_name = match snap_dir.file_name().and_then(|n| n.to_str()) {
+            Some("bin") | None => continue,
+            Some(name) => name.to_owned()
+        };
+        for install_dir in find_install_dirs(&snap_dir.join("current").join("usr").join("lib"), 1) {
+            if let Some(mut browser) = read_browser(&install_dir, &install_dir, InstallType::Snap) {
+                // Snaps have to be started through their wrapper to be confined properly
+                browser.binary = Path::new("/snap/bin").join(&snap_name);
+                browsers.push(browser);
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn discover_tarball_browsers(browsers: &mut Vec<InstalledBrowser>) {
+    let mut roots = vec![PathBuf::from("/opt")];
+    if let Some(base_dirs) = directories::BaseDirs::new() {
+        roots.push(base_dirs.home_dir().join("opt"));
+        roots.push(base_dirs.home_dir().join(".local").join("opt"));
+    }
+
+    // Some browsers nest the actual installation, e.g. ~/opt/mullvad-browser/Browser
+    for install_dir in roots.iter().flat_map(|r| find_install_dirs(r, 2)) {
+        if let Some(browser) = read_browser(&install_dir, &install_dir, InstallType::Tarball) {
+            browsers.push(browser);
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn discover_desktop_browsers(browsers: &mut Vec<InstalledBrowser>) {
+    for entry in read_desktop_entries().iter().filter(|e| is_browser_desktop_entry(e)) {
+        let browser = match browser_for_desktop_entry(entry) {
+            Some(b) => b,
+            None => continue
+        };
+
+        // Launch browsers we already know about the same way the desktop does
+        let existing = browser.install_dir.as_ref()
+            .and_then(|dir| browsers.iter_mut().find(|b| b.install_dir.as_ref().map_or(false, |d| same_path(d, dir))));
+        match existing {
+            Some(existing) => if existing.launch_template.is_none() {
+                existing.launch_template = browser.launch_template;
+                existing.desktop_entry = browser.desktop_entry;
+            },
+            None => browsers.push(browser)
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn is_browser_family_name(name: &str) -> bool {
+    let name = name.to_lowercase();
+    BROWSER_FAMILY_NAMES.iter()
+        .any(|f| name == *f || name.starts_with(&format!("{}-", f)) || name.starts_with(&format!("{}_", f)))
+}
+
+// The program a desktop entry runs, looking through `env VAR=value program`
+#[cfg(target_os = "linux")]
+fn desktop_entry_program(entry: &DesktopEntry) -> &str {
+    let exec = &entry.exec;
+    if Path::new(&exec.program).file_name().map_or(false, |n| n == "env") {
+        if let Some(program) = exec.args_before.iter().find(|a| !a.contains('=') && !a.starts_with('-')) {
+            return program;
+        }
+    }
+    &exec.program
+}
+
+#[cfg(target_os = "linux")]
+fn is_browser_desktop_entry(entry: &DesktopEntry) -> bool {
+    let program_name = Path::new(desktop_entry_program(entry))
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or_default();
+    is_browser_family_name(program_name)
+        || entry.startup_wm_class.as_deref().map_or(false, is_browser_family_name)
+        // e.g. org.mozilla.firefox or io.gitlab.librewolf-community
+        || entry.flatpak_app_id.as_deref()
+            .and_then(|id| id.rsplit('.').next())
+            .map_or(false, is_browser_family_name)
+}
+
+#[cfg(target_os = "linux")]
+fn browser_for_desktop_entry(entry: &DesktopEntry) -> Option<InstalledBrowser> {
+    let program = match resolve_program(desktop_entry_program(entry)) {
+        Some(p) => p,
+        None => {
+            log::trace!("Program of desktop entry {:?} is not installed", entry.path);
+            return None;
+        }
+    };
+
+    let (install_dir, install_type) = if let Some(app_id) = &entry.flatpak_app_id {
+        let install_dir = flatpak_installations().iter()
+            .flat_map(|i| find_install_dirs(&i.join("app").join(app_id).join("current").join("active").join("files"), 2))
+            .next();
+        (install_dir, InstallType::Flatpak)
+    } else if let Ok(snap_name) = program.strip_prefix("/snap/bin") {
+        let install_dir = find_install_dirs(&Path::new("/snap").join(snap_name).join("current").join("usr").join("lib"), 1)
+            .into_iter()
+            .next();
+        (install_dir, InstallType::Snap)
+    } else {
+        // Follow symlinks like /usr/bin/firefox -> /usr/lib/firefox/firefox
+        let install_dir = fs::canonicalize(&program)
+            .ok()
+            .and_then(|p| p.parent().map(Path::to_path_buf))
+            .filter(|d| is_install_dir(d));
+        let in_home = directories::BaseDirs::new().map_or(false, |d| program.starts_with(d.home_dir()));
+        let install_type = if in_home || program.starts_with("/opt") {
+            InstallType::Tarball
+        } else {
+            InstallType::Distro
+        };
+        (install_dir, install_type)
+    };
+
+    let mut browser = match install_dir.as_ref().and_then(|d| read_browser(d, d, install_type)) {
+        Some(browser) => browser,
+        // Custom launchers, all we know about them is what the desktop entry says
+        None => InstalledBrowser {
+            product_name: entry.name.clone(),
+            version: None,
+            channel: channel_from_name(&entry.name),
+            install_type,
+            binary: program.clone(),
+            install_dir: None,
+            desktop_entry: None,
+            launch_template: None
+        }
+    };
+    // The binary inside a Flatpak can only be launched through Flatpak
+    if install_type != InstallType::Flatpak {
+        browser.binary = program;
+    }
+    browser.desktop_entry = Some(entry.path.clone());
+    browser.launch_template = Some(entry.exec.clone());
+    Some(browser)
+}
+
+#[cfg(target_os = "linux")]
+fn channel_from_name(name: &str) -> BrowserChannel {
+    let name = name.to_lowercase();
+    if name.contains("nightly") {
+        BrowserChannel::Nightly
+    } else if name.contains("developer") {
+        BrowserChannel::DeveloperEdition
+    } else if name.contains("esr") {
+        BrowserChannel::Esr
+    } else if name.contains("beta") {
+        BrowserChannel::Beta
+    } else {
+        BrowserChannel::Release
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn same_path(a: &Path, b: &Path) -> bool {
+    a == b || match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn discover_macos_browsers(browsers: &mut Vec<InstalledBrowser>) {
+    let mut roots = vec![PathBuf::from("/Applications")];
+    if let Some(base_dirs) = directories::BaseDirs::new() {
+        roots.push(base_dirs.home_dir().join("Applications"));
+    }
+
+    // Paths look like this: /Applications/Firefox.app/Contents/Resources/application.ini
+    for app_dir in roots.iter().flat_map(|r| list_dirs(r)) {
+        let contents_dir = app_dir.join("Contents");
+        let install_dir = contents_dir.join("Resources");
+        if is_install_dir(&install_dir) {
+            if let Some(browser) = read_browser(&install_dir, &contents_dir.join("MacOS"), InstallType::Installer) {
+                browsers.push(browser);
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "windows")]
+fn discover_windows_browsers(browsers: &mut Vec<InstalledBrowser>) {
+    let mut roots = vec![
+        env::var("ProgramFiles").unwrap_or_else(|_| String::from("C:\\Program Files")),
+        env::var("ProgramFiles(x86)").unwrap_or_else(|_| String::from("C:\\Program Files (x86)"))
+    ];
+    // Per-user installs
+    if let Ok(local_app_data) = env::var("LOCALAPPDATA") {
+        roots.push(format!("{}\\Programs", local_app_data));
+    }
+
+    for install_dir in roots.iter().flat_map(|r| find_install_dirs(Path::new(r), 1)) {
+        if let Some(browser) = read_browser(&install_dir, &install_dir, InstallType::Installer) {
+            browsers.push(browser);
+        }
+    }
+}
+
+// List the directories in a directory, sorted by name so the results are stable
+fn list_dirs(dir: &Path) -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| p.is_dir())
+            .collect(),
+        Err(_) => return Vec::new()
+    };
+    dirs.sort();
+    dirs
+}
+
+// Find browser installations at most `depth` directories below the root
+fn find_install_dirs(root: &Path, depth: usize) -> Vec<PathBuf> {
+    if depth == 0 {
+        return Vec::new();
+    }
+    list_dirs(root)
+        .into_iter()
+        .flat_map(|dir| if is_install_dir(&dir) {
+            vec![dir]
+        } else {
+            find_install_dirs(&dir, depth - 1)
+        })
+        .collect()
+}
+
+/// Check whether a binary belongs to a Firefox-family browser.
+pub fn is_browser_binary(path: &Path) -> bool {
+    let known_name = path.file_stem()
+        .and_then(|n| n.to_str())
+        .map_or(false, |n| BROWSER_EXECUTABLES.contains(&n.trim_end_matches("-bin")));
+    known_name || path.parent().map_or(false, is_install_dir)
+}
+
+// Other Mozilla applications (e.g. Thunderbird) have an application.ini too, but only browsers have a "browser" dir
+fn is_install_dir(dir: &Path) -> bool {
+    dir.join("application.ini").is_file() && dir.join("browser").is_dir()
+}
+
+fn read_browser(install_dir: &Path, exe_dir: &Path, install_type: InstallType) -> Option<InstalledBrowser> {
+    let app_ini = match ini::Ini::load_from_file(install_dir.join("application.ini")) {
+        Ok(ini) => ini,
+        Err(e) => {
+            log::warn!("Failed to read application.ini of browser at {:?}: {:?}", install_dir, e);
+            return None;
+        }
+    };
+    let product_name = app_ini.get_from(Some("App"), "Name")?.to_owned();
+    let version = app_ini.get_from(Some("App"), "Version").map(str::to_owned);
+
+    let binary = find_executable(exe_dir, &product_name);
+    if binary.is_none() {
+        log::trace!("Could not find the executable of browser at {:?}", install_dir);
+    }
+
+    Some(InstalledBrowser {
+        channel: detect_channel(install_dir, version.as_deref()),
+        product_name,
+        version,
+        install_type,
+        binary: binary?,
+        install_dir: Some(install_dir.to_path_buf()),
+        desktop_entry: None,
+        launch_template: None
+    })
+}
+
+fn find_executable(exe_dir: &Path, product_name: &str) -> Option<PathBuf> {
+    let product_exe = product_name.to_lowercase().replace(' ', "");
+    BROWSER_EXECUTABLES.iter()
+        .copied()
+        .chain(std::iter::once(product_exe.as_str()))
+        .map(|name| exe_dir.join(format!("{}{}", name, env::consts::EXE_SUFFIX)))
+        .find(|p| p.is_file())
+}
+
+fn detect_channel(install_dir: &Path, version: Option<&str>) -> BrowserChannel {
+    let update_channel = fs::read_to_string(install_dir.join("defaults").join("pref").join("channel-prefs.js"))
+        .ok()
+        .and_then(|prefs| CHANNEL_PREF_REGEX.captures(&prefs).map(|c| c[1].to_owned()));
+
+    match update_channel.as_deref() {
+        Some("release") => BrowserChannel::Release,
+        Some("beta") => BrowserChannel::Beta,
+        Some("aurora") => BrowserChannel::DeveloperEdition,
+        Some("nightly") => BrowserChannel::Nightly,
+        Some(c) if c.starts_with("esr") => BrowserChannel::Esr,
+        // Distribution builds use the "default" channel, guess from the version instead (e.g. 115.4.0esr, 121.0a1, 120.0b3)
+        _ => match version {
+            Some(v) if v.contains("esr") => BrowserChannel::Esr,
+            Some(v) if v.contains('a') => BrowserChannel::Nightly,
+            Some(v) if v.contains('b') => BrowserChannel::Beta,
+            _ => BrowserChannel::Release
+        }
+    }
+}
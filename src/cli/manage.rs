@@ -0,0 +1,118 @@
+use std::collections::HashMap;
+use crate::cli::find_profile;
+use crate::cmd::execute_cmd_for_message;
+use crate::native_req::{NativeMessage, NativeMessageCreateProfile, NativeMessageDeleteProfile, NativeMessageLaunchProfile, NativeMessageLaunchWorkspace, NativeMessageUpdateProfile, NativeMessageUpdateProfileOrder};
+use crate::native_resp::{NativeResponse, NativeResponseData, NativeResponseProfileListProfileEntry};
+use crate::profiles::{read_profiles, ProfileEntry};
+use crate::profiles_order::OrderData;
+use crate::state::AppContext;
+use crate::workspaces::WorkspaceData;
+
+macro_rules! profiles {
+    ($context:ident)=>{
+        match read_profiles(&$context.state().config, &$context.state().config_dir) {
+            Ok(p) => p,
+            Err(e) => {
+                return NativeResponse::error_with_dbg_msg("Failed to load profile list.", e);
+            }
+        }
+    };
+}
+
+macro_rules! profile {
+    ($profiles:ident, $name_or_id:expr)=>{
+        match find_profile(&$profiles, $name_or_id) {
+            Some(p) => p,
+            None => {
+                return NativeResponse::error(format!("No profile named {:?} could be found!", $name_or_id));
+            }
+        }
+    };
+}
+
+pub fn cli_list(context: &AppContext) -> NativeResponse {
+    let profiles = profiles!(context);
+    let mut order_data = OrderData::read(&context.state().config_dir);
+    order_data.recalculate(&profiles);
+
+    let profiles = order_data.order.iter()
+        .filter_map(|id| profiles.profile_entries.iter().find(|p| &p.id == id))
+        .map(NativeResponseProfileListProfileEntry::from_profile_entry)
+        .collect();
+    NativeResponse::success(NativeResponseData::ProfileList { profiles })
+}
+
+pub fn cli_launch(context: &AppContext, profile: &str, url: Option<String>) -> NativeResponse {
+    let profiles = profiles!(context);
+    let profile = profile!(profiles, profile);
+    execute_cmd_for_message(context, NativeMessage::LaunchProfile(NativeMessageLaunchProfile {
+        profile_id: profile.id.clone(),
+        url,
+        new_window: false
+    }))
+}
+
+pub fn cli_create(context: &AppContext, name: String, avatar: Option<String>) -> NativeResponse {
+    let profiles = profiles!(context);
+    // Without an explicit avatar, reuse the one of the default profile
+    let avatar = avatar
+        .or_else(|| profiles.profile_entries.iter()
+            .find(|p| p.default)
+            .and_then(|p| p.avatar.clone()))
+        .unwrap_or_default();
+    execute_cmd_for_message(context, NativeMessage::CreateProfile(NativeMessageCreateProfile {
+        name,
+        avatar,
+        options: HashMap::new(),
+        root_id: None
+    }))
+}
+
+pub fn cli_rename(context: &AppContext, profile: &str, name: String) -> NativeResponse {
+    let profiles = profiles!(context);
+    let profile = profile!(profiles, profile);
+    update_profile(context, profile, name, false)
+}
+
+pub fn cli_delete(context: &AppContext, profile: &str) -> NativeResponse {
+    let profiles = profiles!(context);
+    let profile = profile!(profiles, profile);
+    execute_cmd_for_message(context, NativeMessage::DeleteProfile(NativeMessageDeleteProfile {
+        profile_id: profile.id.clone()
+    }))
+}
+
+pub fn cli_set_default(context: &AppContext, profile: &str) -> NativeResponse {
+    let profiles = profiles!(context);
+    let profile = profile!(profiles, profile);
+    update_profile(context, profile, profile.name.clone(), true)
+}
+
+pub fn cli_order(context: &AppContext, order: &[String]) -> NativeResponse {
+    let profiles = profiles!(context);
+    let mut profile_ids = Vec::new();
+    for name_or_id in order {
+        profile_ids.push(profile!(profiles, name_or_id).id.clone());
+    }
+    execute_cmd_for_message(context, NativeMessage::UpdateProfileOrder(NativeMessageUpdateProfileOrder {
+        order: profile_ids
+    }))
+}
+
+fn update_profile(context: &AppContext, profile: &ProfileEntry, name: String, default: bool) -> NativeResponse {
+    execute_cmd_for_message(context, NativeMessage::UpdateProfile(NativeMessageUpdateProfile {
+        profile_id: profile.id.clone(),
+        name,
+        avatar: profile.avatar.clone(),
+        options: profile.options.clone(),
+        default
+    }))
+}
+
+pub fn cli_launch_workspace(context: &AppContext, workspace: &str) -> NativeResponse {
+    let workspace_id = match WorkspaceData::read(&context.state().config_dir).find(workspace) {
+        Some(w) => w.id.clone(),
+        None => return NativeResponse::error(format!("No workspace named {:?} could be found!", workspace))
+    };
+    execute_cmd_for_message(context, NativeMessage::LaunchWorkspace(NativeMessageLaunchWorkspace { workspace_id }))
+}
@@ -0,0 +1,212 @@
+mod open_url;
+mod manage;
+
+use std::path::Path;
+use crate::native_resp::{NativeResponse, NativeResponseData};
+use crate::profiles::{ProfileEntry, ProfilesIniState};
+use crate::state::AppContext;
+use crate::cli::open_url::cli_open_url;
+use crate::cli::manage::{cli_create, cli_delete, cli_launch, cli_launch_workspace, cli_list, cli_order, cli_rename, cli_set_default};
+
+// === COMMAND LINE ===
+
+const USAGE: &str = concat!(
+    "Usage:\n",
+    "  ff-pswitch-connector <url>\n",
+    "  ff-pswitch-connector --open <url> [--profile <name|id>] [--remember]\n",
+    "  ff-pswitch-connector list [--json]\n",
+    "  ff-pswitch-connector launch <name|id> [url] [--json]\n",
+    "  ff-pswitch-connector create <name> [--avatar <avatar>] [--json]\n",
+    "  ff-pswitch-connector rename <name|id> <new name> [--json]\n",
+    "  ff-pswitch-connector delete <name|id> [--json]\n",
+    "  ff-pswitch-connector set-default <name|id> [--json]\n",
+    "  ff-pswitch-connector order <name|id>... [--json]\n",
+    "  ff-pswitch-connector workspace <name|id> [--json]\n",
+    "\n",
+    "Global options (also read from FPS_CONFIG_DIR, FPS_DATA_DIR and FPS_PROFILE_ROOT):\n",
+    "  --config-dir <dir>    Directory to read the connector's configuration from\n",
+    "  --data-dir <dir>      Directory to keep logs and avatars in\n",
+    "  --profile-root <dir>  Directory containing the browser's profiles.ini\n"
+);
+
+// Things the connector can do when it is started outside of the browser
+pub enum CliAction {
+    OpenUrl { url: String, profile: Option<String>, remember: bool },
+    List,
+    Launch { profile: String, url: Option<String> },
+    Create { name: String, avatar: Option<String> },
+    Rename { profile: String, name: String },
+    Delete { profile: String },
+    SetDefault { profile: String },
+    Order { profiles: Vec<String> },
+    LaunchWorkspace { workspace: String },
+    Usage { error: String }
+}
+
+pub struct CliCommand {
+    action: CliAction,
+    // Print the raw response for scripts instead of a human readable summary
+    json: bool
+}
+
+/// Figure out whether we were started from the command line instead of by the browser. Browsers
+/// always pass the path to our native messaging manifest as the first argument.
+pub fn parse_cli_command(args: &[String]) -> Option<CliCommand> {
+    let first_arg = args.get(1)?;
+    let json = args.iter().skip(1).any(|a| a == "--json");
+    let rest: Vec<&str> = args.iter()
+        .skip(2)
+        .map(String::as_str)
+        .filter(|a| *a != "--json")
+        .collect();
+
+    let action = match first_arg.as_str() {
+        "list" => match rest[..] {
+            [] => CliAction::List,
+            _ => usage_error("list does not take any arguments.")
+        },
+        "launch" => match rest[..] {
+            [profile] => CliAction::Launch { profile: profile.to_owned(), url: None },
+            [profile, url] => CliAction::Launch { profile: profile.to_owned(), url: Some(url.to_owned()) },
+            _ => usage_error("launch takes a profile and an optional URL.")
+        },
+        "create" => match rest[..] {
+            [name] => CliAction::Create { name: name.to_owned(), avatar: None },
+            [name, "--avatar", avatar] => CliAction::Create { name: name.to_owned(), avatar: Some(avatar.to_owned()) },
+            _ => usage_error("create takes a profile name and an optional avatar.")
+        },
+        "rename" => match rest[..] {
+            [profile, name] => CliAction::Rename { profile: profile.to_owned(), name: name.to_owned() },
+            _ => usage_error("rename takes a profile and its new name.")
+        },
+        "delete" => match rest[..] {
+            [profile] => CliAction::Delete { profile: profile.to_owned() },
+            _ => usage_error("delete takes a profile.")
+        },
+        "set-default" => match rest[..] {
+            [profile] => CliAction::SetDefault { profile: profile.to_owned() },
+            _ => usage_error("set-default takes a profile.")
+        },
+        "order" => match rest[..] {
+            [] => usage_error("order takes the profiles in their new order."),
+            _ => CliAction::Order { profiles: rest.iter().map(|p| p.to_string()).collect() }
+        },
+        "workspace" => match rest[..] {
+            [workspace] => CliAction::LaunchWorkspace { workspace: workspace.to_owned() },
+            _ => usage_error("workspace takes a workspace.")
+        },
+        a if a == "--open" || looks_like_url(a) => parse_open_url(args),
+        _ => return None
+    };
+
+    Some(CliCommand { action, json })
+}
+
+fn parse_open_url(args: &[String]) -> CliAction {
+    let mut url = None::<String>;
+    let mut profile = None::<String>;
+    let mut remember = false;
+    let mut args = args.iter().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--open" => match args.next() {
+                Some(u) => url = Some(u.clone()),
+                None => return usage_error("Missing URL after --open.")
+            },
+            "--profile" => match args.next() {
+                Some(p) => profile = Some(p.clone()),
+                None => return usage_error("Missing profile after --profile.")
+            },
+            "--remember" => remember = true,
+            "--json" => {}
+            a if url.is_none() && looks_like_url(a) => url = Some(a.to_owned()),
+            a => return usage_error(&format!("Unknown argument: {}", a))
+        }
+    }
+
+    match url {
+        Some(url) => CliAction::OpenUrl { url, profile, remember },
+        None => usage_error("No URL to open was specified.")
+    }
+}
+
+fn usage_error(error: &str) -> CliAction {
+    CliAction::Usage { error: error.to_owned() }
+}
+
+// Windows paths (e.g. C:\foo) parse as URLs too, so only accept schemes a browser would be handed
+fn looks_like_url(arg: &str) -> bool {
+    match url::Url::parse(arg) {
+        Ok(url) => matches!(url.scheme(), "http" | "https" | "file") && !Path::new(arg).exists(),
+        Err(_) => false
+    }
+}
+
+/// Run the command and return the exit code of the process.
+pub fn run_cli_command(context: &AppContext, command: CliCommand) -> i32 {
+    let response = match command.action {
+        CliAction::OpenUrl { url, profile, remember } => cli_open_url(context, url, profile, remember),
+        CliAction::List => cli_list(context),
+        CliAction::Launch { profile, url } => cli_launch(context, &profile, url),
+        CliAction::Create { name, avatar } => cli_create(context, name, avatar),
+        CliAction::Rename { profile, name } => cli_rename(context, &profile, name),
+        CliAction::Delete { profile } => cli_delete(context, &profile),
+        CliAction::SetDefault { profile } => cli_set_default(context, &profile),
+        CliAction::Order { profiles } => cli_order(context, &profiles),
+        CliAction::LaunchWorkspace { workspace } => cli_launch_workspace(context, &workspace),
+        CliAction::Usage { error } => {
+            eprintln!("{}\n\n{}", error, USAGE);
+            return 2;
+        }
+    };
+
+    log::trace!("CLI command finished, response is: {:?}", response);
+
+    let success = matches!(response, NativeResponse::Success { .. });
+    if command.json {
+        match serde_json::to_string_pretty(&response) {
+            Ok(json) => println!("{}", json),
+            Err(e) => {
+                eprintln!("Failed to serialize response: {:?}", e);
+                return 1;
+            }
+        }
+    } else {
+        print_response(&response);
+    }
+
+    if success { 0 } else { 1 }
+}
+
+fn print_response(response: &NativeResponse) {
+    match response {
+        NativeResponse::Error { error, debug_msg, .. } => {
+            eprintln!("{}", error);
+            if let Some(debug_msg) = debug_msg {
+                eprintln!("{}", debug_msg);
+            }
+        }
+        NativeResponse::Success { data, .. } => match data {
+            NativeResponseData::ProfileList { profiles } => {
+                for profile in profiles {
+                    let browser = profile.browser.as_ref().map(|b| format!("\t({})", b)).unwrap_or_default();
+                    println!("{} {}\t{}{}", if profile.default { "*" } else { " " }, profile.id, profile.name, browser);
+                }
+            }
+            NativeResponseData::ProfileCreated { profile } => println!("Created profile {} ({})", profile.name, profile.id),
+            NativeResponseData::ProfileUpdated { profile } => println!("Updated profile {} ({})", profile.name, profile.id),
+            NativeResponseData::ProfileDeleted => println!("Profile deleted."),
+            NativeResponseData::WorkspaceLaunched => println!("Workspace launched."),
+            _ => {}
+        }
+        NativeResponse::Event(_) => {}
+    }
+}
+
+// Find a profile by its ID or its name
+pub fn find_profile<'a>(profiles: &'a ProfilesIniState, name_or_id: &str) -> Option<&'a ProfileEntry> {
+    let name = name_or_id.trim();
+    profiles.profile_entries.iter()
+        .find(|p| p.id == name_or_id)
+        .or_else(|| profiles.profile_entries.iter().find(|p| p.name.trim().eq_ignore_ascii_case(name)))
+}
@@ -0,0 +1,47 @@
+use crate::cli::find_profile;
+use crate::cmd::execute_cmd_for_message;
+use crate::native_req::{NativeMessage, NativeMessageLaunchProfile};
+use crate::native_resp::NativeResponse;
+use crate::profiles::read_profiles;
+use crate::routing::RoutingData;
+use crate::state::AppContext;
+
+pub fn cli_open_url(context: &AppContext, url: String, profile: Option<String>, remember: bool) -> NativeResponse {
+    let profiles = match read_profiles(&context.state().config, &context.state().config_dir) {
+        Ok(p) => p,
+        Err(e) => return NativeResponse::error_with_dbg_msg("Failed to load profile list.", e)
+    };
+    let mut routing_data = RoutingData::read(&context.state().config_dir);
+
+    // An explicit choice wins, then the routing rules, then the remembered choice and finally the default profile
+    let target = match &profile {
+        Some(profile) => match find_profile(&profiles, profile) {
+            Some(p) => p,
+            None => return NativeResponse::error(format!("No profile named {:?} could be found!", profile))
+        },
+        None => match routing_data.route(&profiles, &url)
+            .or_else(|| routing_data.fallback_profile_id.as_ref()
+                .and_then(|id| profiles.profile_entries.iter().find(|p| &p.id == id)))
+            .or_else(|| profiles.profile_entries.iter().find(|p| p.default))
+            .or_else(|| profiles.profile_entries.first()) {
+            Some(p) => p,
+            None => return NativeResponse::error("There are no profiles to open the URL in!")
+        }
+    };
+
+    log::trace!("Opening URL {} from command line in profile: {}", url, target.id);
+
+    if remember && profile.is_some() {
+        routing_data.fallback_profile_id = Some(target.id.clone());
+        if let Err(e) = routing_data.write_and_notify(context, &profiles) {
+            log::error!("Failed to remember profile choice: {:?}", e);
+        }
+    }
+
+    // Forwards the URL to the running instance or launches a new one
+    execute_cmd_for_message(context, NativeMessage::LaunchProfile(NativeMessageLaunchProfile {
+        profile_id: target.id.clone(),
+        url: Some(url),
+        new_window: false
+    }))
+}
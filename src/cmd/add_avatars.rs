@@ -8,8 +8,13 @@ use crate::profiles::ProfilesIniState;
 use crate::storage::{custom_avatars_path};
 
 pub fn process_cmd_add_avatars(context: &AppContext, profiles: ProfilesIniState) -> NativeResponse {
+    let windowing = match &context.windowing {
+        Some(w) => w,
+        None => return NativeResponse::error("Avatars can only be picked from within the browser.")
+    };
+
     // Pick avatar
-    let result = match context.windowing.open_avatar_picker() {
+    let result = match windowing.open_avatar_picker() {
         Some(r) => r,
         None => Vec::new()
     };
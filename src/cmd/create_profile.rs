@@ -1,17 +1,16 @@
 use crate::state::AppState;
 use serde_json::Value;
-use crate::profiles::{ProfilesIniState, ProfileEntry, calc_profile_id, write_profiles};
+use crate::profiles::{ProfilesIniState, ProfileEntry, calc_profile_id, qualify_profile_id, write_profiles};
 use crate::native_req::NativeMessageCreateProfile;
 use crate::native_resp::{NativeResponse, NativeResponseProfileListProfileEntry, NativeResponseData};
 use ulid::Ulid;
 use std::fs;
 use std::fs::OpenOptions;
 use std::path::PathBuf;
-use crate::ipc::notify_profile_changed;
+use crate::ipc::{notify_profile_changed, notify_update_profile_order};
 use serde::Serialize;
 use std::ops::Add;
 use crate::AppContext;
-use crate::profiles_order::OrderData;
 
 fn find_extension_chunk<'a>(app_state: &AppState, json: &'a Value) -> Option<&'a serde_json::Map<String, Value>> {
     if let Some(our_extension_id) = &app_state.extension_id {
@@ -65,20 +64,30 @@ pub fn process_cmd_create_profile(
         return NativeResponse::error("A profile with this name already exists. Please choose another name.");
     }
 
+    let browser = match &msg.root_id {
+        Some(root_id) => match context.state().config.extra_profile_root(root_id) {
+            Some(root) => Some(root.name.clone().unwrap_or_else(|| root.id.clone())),
+            None => return NativeResponse::error("The profile root to create the profile in does not exist!")
+        },
+        None => None
+    };
+
     let new_profile_path = "profile-".to_owned() + &Ulid::new().to_string();
 
     let new_profile = ProfileEntry {
-        id: calc_profile_id(&new_profile_path, true),
+        id: qualify_profile_id(msg.root_id.as_deref(), &calc_profile_id(&new_profile_path, true)),
         name: new_trimmed_name.to_owned(),
         is_relative: true,
         path: new_profile_path,
         default: false,
         avatar: Some(msg.avatar),
-        options: msg.options
+        options: msg.options,
+        root_id: msg.root_id,
+        browser
     };
 
     // Firefox will refuse to launch if we do not mkdirs the new profile folder
-    let new_profile_full_path = new_profile.full_path(&context.state.config);
+    let new_profile_full_path = new_profile.full_path(&context.state().config);
     if let Err(e) = fs::create_dir_all(&new_profile_full_path) {
         return NativeResponse::error_with_dbg_msg("Failed to folder for new profile!", e);
     }
@@ -86,15 +95,15 @@ pub fn process_cmd_create_profile(
     // Inject extension into new profiles
     // TODO Extract this into function to fix this huge if-let chain
     {
-        if let Some(our_profile) = profiles.profile_entries.iter().find(|p| Some(&p.id) == context.state.cur_profile_id.as_ref()) {
+        if let Some(our_profile) = profiles.profile_entries.iter().find(|p| Some(&p.id) == context.state().cur_profile_id.as_ref()) {
             // Read current extensions JSON
-            let mut extensions_path = our_profile.full_path(&context.state.config);
+            let mut extensions_path = our_profile.full_path(&context.state().config);
             extensions_path.push("extensions.json");
             if let Ok(extensions_file) = OpenOptions::new()
                 .read(true)
                 .open(extensions_path) {
                 if let Ok(extensions_json) = serde_json::from_reader(extensions_file) {
-                    if let Some(mut extension_chunk) = find_extension_chunk(context.state, &extensions_json).cloned() {
+                    if let Some(mut extension_chunk) = find_extension_chunk(&context.state(), &extensions_json).cloned() {
                         let mut old_extension_path: Option<PathBuf> = None;
                         let mut new_extension_path: Option<PathBuf> = None;
 
@@ -103,7 +112,7 @@ pub fn process_cmd_create_profile(
                             if let Value::String(path) = path_entry.get() {
                                 let extension_path = PathBuf::from(path);
                                 if let Some(extension_filename) = extension_path.file_name() {
-                                    let mut new_extension_path_builder = new_profile.full_path(&context.state.config);
+                                    let mut new_extension_path_builder = new_profile.full_path(&context.state().config);
                                     new_extension_path_builder.push("extensions");
                                     new_extension_path_builder.push(extension_filename);
                                     path_entry.insert(Value::String(new_extension_path_builder.to_string_lossy().to_string()));
@@ -166,21 +175,15 @@ pub fn process_cmd_create_profile(
         }
     }
 
-    let resp = NativeResponseProfileListProfileEntry {
-        id: new_profile.id.clone(),
-        name: new_profile.name.clone(),
-        default: new_profile.default,
-        avatar: new_profile.avatar.clone(),
-        options: new_profile.options.clone()
-    };
+    let resp = NativeResponseProfileListProfileEntry::from_profile_entry(&new_profile);
     profiles.profile_entries.push(new_profile);
-    // Re-calculate profile order
-    OrderData::try_rewrite(context, &profiles);
 
-    if let Err(e) = write_profiles(&context.state.config, &context.state.config_dir, &profiles) {
+    if let Err(e) = write_profiles(&context.state().config, &context.state().config_dir, &profiles) {
         return NativeResponse::error_with_dbg_msg("Failed to save new changes!", e);
     }
     notify_profile_changed(context, &profiles);
+    // The profile order was re-calculated along with the profiles
+    notify_update_profile_order(context, &profiles);
 
     return NativeResponse::success(NativeResponseData::ProfileCreated { profile: resp })
 }
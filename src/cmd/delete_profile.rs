@@ -1,10 +1,9 @@
 use crate::profiles::{check_profile_active, ProfilesIniState, write_profiles};
 use crate::native_req::NativeMessageDeleteProfile;
 use crate::native_resp::{NativeResponse, NativeResponseData};
-use crate::ipc::notify_profile_changed;
+use crate::ipc::{notify_profile_changed, notify_update_profile_order};
 use std::fs;
 use crate::AppContext;
-use crate::profiles_order::OrderData;
 
 pub fn process_cmd_delete_profile(context: &AppContext, mut profiles: ProfilesIniState, msg: NativeMessageDeleteProfile) -> NativeResponse {
     let profile_index = match profiles.profile_entries.iter().position(|p| p.id == msg.profile_id) {
@@ -15,7 +14,7 @@ pub fn process_cmd_delete_profile(context: &AppContext, mut profiles: ProfilesIn
     // Delete profile from profile list (but do not write new list yet)
     let profile = profiles.profile_entries.remove(profile_index);
 
-    let profile_path = profile.full_path(&context.state.config);
+    let profile_path = profile.full_path(&context.state().config);
 
     // Check that profile is closed
     if check_profile_active(&profile_path) {
@@ -31,21 +30,21 @@ pub fn process_cmd_delete_profile(context: &AppContext, mut profiles: ProfilesIn
     // Delete profile files
     fs::remove_dir_all(profile_path);
 
-    // Make another profile the default
+    // Make another profile of the same root the default
     if profile.default {
-        if let Some(new_def_profile) = profiles.profile_entries.first_mut() {
+        if let Some(new_def_profile) = profiles.profile_entries.iter_mut().find(|p| p.root_id == profile.root_id) {
             new_def_profile.default = true
         }
     }
 
-    // Re-calculate profile order
-    OrderData::try_rewrite(context, &profiles);
 
     // Write new profile list
-    if let Err(e) = write_profiles(&context.state.config, &context.state.config_dir, &profiles) {
+    if let Err(e) = write_profiles(&context.state().config, &context.state().config_dir, &profiles) {
         return NativeResponse::error_with_dbg_msg("Failed to save new changes!", e);
     }
     notify_profile_changed(context, &profiles);
+    // The profile order was re-calculated along with the profiles
+    notify_update_profile_order(context, &profiles);
 
     return NativeResponse::success(NativeResponseData::ProfileDeleted)
 }
@@ -0,0 +1,18 @@
+use crate::AppContext;
+use crate::diagnostics::{read_diagnostics, resolve_diagnostic};
+use crate::native_req::NativeMessageDismissDiagnostic;
+use crate::native_resp::{NativeResponse, NativeResponseData};
+
+pub fn process_cmd_dismiss_diagnostic(context: &AppContext,
+                                      msg: NativeMessageDismissDiagnostic) -> NativeResponse {
+    let config_dir = &context.state().config_dir;
+    if !read_diagnostics(config_dir).iter().any(|d| d.store == msg.store) {
+        return NativeResponse::error("There is no problem with this store!");
+    }
+
+    resolve_diagnostic(&config_dir.join(&msg.store));
+
+    NativeResponse::success(NativeResponseData::Diagnostics {
+        diagnostics: read_diagnostics(config_dir)
+    })
+}
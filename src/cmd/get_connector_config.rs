@@ -0,0 +1,8 @@
+use crate::AppContext;
+use crate::native_resp::{NativeResponse, NativeResponseConnectorConfig, NativeResponseData};
+
+pub fn process_cmd_get_connector_config(context: &AppContext) -> NativeResponse {
+    NativeResponse::success(NativeResponseData::ConnectorConfig {
+        config: NativeResponseConnectorConfig::from_config(&context.state().config)
+    })
+}
@@ -0,0 +1,9 @@
+use crate::AppContext;
+use crate::diagnostics::read_diagnostics;
+use crate::native_resp::{NativeResponse, NativeResponseData};
+
+pub fn process_cmd_get_diagnostics(context: &AppContext) -> NativeResponse {
+    NativeResponse::success(NativeResponseData::Diagnostics {
+        diagnostics: read_diagnostics(&context.state().config_dir)
+    })
+}
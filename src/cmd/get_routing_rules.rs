@@ -0,0 +1,11 @@
+use crate::AppContext;
+use crate::native_resp::{NativeResponse, NativeResponseData};
+use crate::routing::RoutingData;
+
+pub fn process_cmd_get_routing_rules(context: &AppContext) -> NativeResponse {
+    let routing_data = RoutingData::read(&context.state().config_dir);
+    NativeResponse::success(NativeResponseData::RoutingRules {
+        rules: routing_data.rules,
+        fallback_profile_id: routing_data.fallback_profile_id
+    })
+}
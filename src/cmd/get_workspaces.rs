@@ -0,0 +1,9 @@
+use crate::AppContext;
+use crate::native_resp::{NativeResponse, NativeResponseData};
+use crate::workspaces::WorkspaceData;
+
+pub fn process_cmd_get_workspaces(context: &AppContext) -> NativeResponse {
+    NativeResponse::success(NativeResponseData::Workspaces {
+        workspaces: WorkspaceData::read(&context.state().config_dir).workspaces
+    })
+}
@@ -1,7 +1,7 @@
 use crate::state::AppState;
 use crate::profiles::{ProfilesIniState, write_profiles};
 use crate::native_req::NativeMessageInitialize;
-use crate::native_resp::{NativeResponse, NativeResponseData, NativeResponseEvent, NativeResponseProfileListProfileEntry, write_native_event};
+use crate::native_resp::{NativeResponse, NativeResponseData, NativeResponseEvent, NativeResponseProfileListProfileEntry, NativeResponseProfileRoot, write_native_event};
 use std::{fs};
 use semver::Version;
 use crate::options::native_notify_updated_options;
@@ -17,10 +17,19 @@ const EXTENSION_STORAGE_PREFIXES: [&str; 4] = [
 pub fn process_cmd_initialize(app_state: &mut AppState,
                               mut profiles: ProfilesIniState,
                               msg: NativeMessageInitialize) -> NativeResponse {
-    if let Some(profile_id) = &msg.profile_id {
+    // IDs cached by the extension change when the browser that launched us becomes an extra root
+    let known_profile_id = msg.profile_id.as_ref()
+        .filter(|id| profiles.profile_entries.iter().any(|p| &p.id == *id));
+    if let (Some(profile_id), None) = (&msg.profile_id, known_profile_id) {
+        log::info!("Profile ID provided by extension is unknown, determining it again: {}", profile_id);
+    }
+    if let Some(profile_id) = known_profile_id {
         log::trace!("Profile ID was provided by extension: {}", profile_id);
         finish_init(app_state, &mut profiles, profile_id, msg.extension_id, msg.extension_version);
-        return NativeResponse::success(NativeResponseData::Initialized { cached: true })
+        return NativeResponse::success(NativeResponseData::Initialized {
+            cached: true,
+            profile_root: NativeResponseProfileRoot::from_resolution(app_state.config.profile_root_resolution())
+        })
     }
 
     // Extension didn't tell us profile id so we have to determine it
@@ -51,11 +60,17 @@ pub fn process_cmd_initialize(app_state: &mut AppState,
             let profile_id = profile.id.clone();
             log::trace!("Profile ID determined: {}", profile_id);
             finish_init(app_state, &mut profiles, &profile_id, msg.extension_id, msg.extension_version);
-            return NativeResponse::success(NativeResponseData::Initialized { cached: false })
+            return NativeResponse::success(NativeResponseData::Initialized {
+                cached: false,
+                profile_root: NativeResponseProfileRoot::from_resolution(app_state.config.profile_root_resolution())
+            })
         }
     }
 
-    return NativeResponse::error("Unable to detect current profile.")
+    // The profiles may belong to another browser than the one that launched us
+    let profile_root = app_state.config.profile_root_resolution();
+    return NativeResponse::error_with_dbg_str("Unable to detect current profile.",
+                                              format!("Profile root: {:?}\n{}", profile_root.root, profile_root.steps.join("\n")))
 }
 
 fn finish_init(
@@ -77,8 +92,9 @@ fn finish_init(
             Some(profile) => {
                 // Set first-run profile as default
                 profile.default = true;
+                let root_id = profile.root_id.clone();
                 for other_profile in profiles.profile_entries.iter_mut() {
-                    if other_profile.id != profile_id {
+                    if other_profile.id != profile_id && other_profile.root_id == root_id {
                         other_profile.default = false
                     }
                 }
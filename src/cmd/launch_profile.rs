@@ -4,6 +4,11 @@ use crate::native_req::NativeMessageLaunchProfile;
 use crate::native_resp::{NativeResponse, NativeResponseData};
 use crate::ipc::notify_focus_window;
 use crate::process::{fork_browser_proc, ForkBrowserProcError};
+use crate::process_registry::{snapshot_profile_lock, LaunchReadinessError};
+use std::time::Duration;
+
+// How long to wait for the browser to start before giving up
+const LAUNCH_TIMEOUT: Duration = Duration::from_secs(20);
 
 pub fn process_cmd_launch_profile(context: &AppContext,
                               profiles: ProfilesIniState,
@@ -16,16 +21,41 @@ pub fn process_cmd_launch_profile(context: &AppContext,
 
     log::trace!("Launching profile: {}", profile.id);
 
-    match notify_focus_window(context, &msg.profile_id, msg.url.clone()) {
+    match notify_focus_window(context, &msg.profile_id, msg.url.clone(), msg.new_window) {
         Ok(_) => { return NativeResponse::success(NativeResponseData::ProfileLaunched); }
         Err(e) => { log::info!("Failed to focus current browser window, launching new window: {:?}", e); }
     }
 
-    match fork_browser_proc(context.state, profile, msg.url) {
-        Ok(_) => NativeResponse::success(NativeResponseData::ProfileLaunched),
+    let profile_path = profile.full_path(&context.state().config);
+    let lock_before = snapshot_profile_lock(&profile_path);
+    match fork_browser_proc(&context.state(), profile, msg.url, msg.new_window) {
+        Ok(Some(spawned)) => {
+            let pending = context.processes.register(&profile.id, spawned);
+            match pending.wait_until_ready(&profile_path, &lock_before, LAUNCH_TIMEOUT) {
+                Ok(readiness) => {
+                    log::trace!("Profile {} launched: {:?}", profile.id, readiness);
+                    NativeResponse::success(NativeResponseData::ProfileLaunched)
+                },
+                Err(LaunchReadinessError::ExitedEarly { code, stderr }) => {
+                    let reason = if stderr.contains("already running") {
+                        "This profile is already open in a browser that is not responding. Close the browser and try again."
+                    } else if stderr.contains("profile cannot be loaded") || stderr.contains("profile missing") {
+                        "The browser could not load this profile, it may be missing or inaccessible."
+                    } else {
+                        "The browser exited while starting."
+                    };
+                    let code = code.map(|c| c.to_string()).unwrap_or_else(|| "none".to_owned());
+                    NativeResponse::error_with_dbg_str(reason, format!("Exit code: {}\n{}", code, stderr))
+                },
+                Err(e @ LaunchReadinessError::ProfileInUse { .. }) => NativeResponse::error_with_dbg_msg(
+                    "This profile is already open in a browser that is not responding. Close the browser and try again.", e),
+                Err(e @ LaunchReadinessError::TimedOut) => NativeResponse::error_with_dbg_msg(
+                    "The browser is taking too long to start. It may be waiting for you to respond to a dialog.", e)
+            }
+        },
+        Ok(None) => NativeResponse::success(NativeResponseData::ProfileLaunched),
         Err(e) => match e {
             ForkBrowserProcError::BadExitCode => NativeResponse::error_with_dbg_msg("Failed to launch browser with new profile (bad exit code)!", e),
-            ForkBrowserProcError::ForkError { .. } => NativeResponse::error_with_dbg_msg("Failed to launch browser with new profile (fork error)!", e),
             ForkBrowserProcError::ProcessLaunchError(_) => NativeResponse::error_with_dbg_msg("Failed to launch browser with new profile!", e),
             ForkBrowserProcError::BinaryNotFound => NativeResponse::error_with_dbg_msg("Unable to find browser binary!", e),
             ForkBrowserProcError::BinaryDoesNotExist => NativeResponse::error(concat!(
@@ -0,0 +1,26 @@
+use crate::AppContext;
+use crate::native_req::NativeMessageLaunchWorkspace;
+use crate::native_resp::{NativeResponse, NativeResponseData};
+use crate::workspaces::{launch_workspace, WorkspaceData};
+
+pub fn process_cmd_launch_workspace(context: &AppContext, msg: NativeMessageLaunchWorkspace) -> NativeResponse {
+    let workspace_data = WorkspaceData::read(&context.state().config_dir);
+    let workspace = match workspace_data.workspaces.iter().find(|w| w.id == msg.workspace_id) {
+        Some(w) => w,
+        None => return NativeResponse::error("No workspace with the specified id could be found!")
+    };
+
+    let failures = launch_workspace(context, workspace);
+    if failures.is_empty() {
+        NativeResponse::success(NativeResponseData::WorkspaceLaunched)
+    } else {
+        let details = failures.iter()
+            .map(|(profile_id, resp)| format!("{}: {:?}", profile_id, resp))
+            .collect::<Vec<String>>()
+            .join("\n");
+        NativeResponse::error_with_dbg_str(
+            format!("{} of the profiles in this workspace could not be launched.", failures.len()),
+            details
+        )
+    }
+}
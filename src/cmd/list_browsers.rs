@@ -0,0 +1,8 @@
+use crate::browsers::discover_browsers;
+use crate::native_resp::{NativeResponse, NativeResponseBrowser, NativeResponseData};
+
+pub fn process_cmd_list_browsers() -> NativeResponse {
+    NativeResponse::success(NativeResponseData::Browsers {
+        browsers: discover_browsers().iter().map(NativeResponseBrowser::from_installed_browser).collect()
+    })
+}
@@ -0,0 +1,20 @@
+use crate::AppContext;
+use crate::native_resp::{NativeResponse, NativeResponseData, NativeResponseProfileRootEntry};
+
+pub fn process_cmd_list_profile_roots(context: &AppContext) -> NativeResponse {
+    let config = &context.state().config;
+    let primary = NativeResponseProfileRootEntry {
+        id: None,
+        name: None,
+        profile_dir: config.browser_profile_dir().to_string_lossy().to_string()
+    };
+    let extra = config.extra_profile_roots().iter().map(|root| NativeResponseProfileRootEntry {
+        id: Some(root.id.clone()),
+        name: Some(root.name.clone().unwrap_or_else(|| root.id.clone())),
+        profile_dir: config.profile_root_dir(Some(&root.id)).to_string_lossy().to_string()
+    });
+
+    NativeResponse::success(NativeResponseData::ProfileRoots {
+        roots: std::iter::once(primary).chain(extra).collect()
+    })
+}
@@ -0,0 +1,11 @@
+use crate::AppContext;
+use crate::profiles::ProfilesIniState;
+use crate::native_resp::{NativeResponse, NativeResponseData, NativeResponseRunningProfile};
+
+pub fn process_cmd_list_running_profiles(context: &AppContext, profiles: ProfilesIniState) -> NativeResponse {
+    let running = context.processes.list_running(&context.state().config, &profiles);
+
+    NativeResponse::success(NativeResponseData::RunningProfiles {
+        profiles: running.iter().map(NativeResponseRunningProfile::from_running_profile).collect()
+    })
+}
@@ -9,6 +9,21 @@ mod add_avatars;
 mod get_avatar;
 mod delete_avatar;
 mod update_profiles_order;
+mod list_running_profiles;
+mod open_url;
+mod get_routing_rules;
+mod update_routing_rules;
+mod get_workspaces;
+mod update_workspaces;
+mod launch_workspace;
+mod list_browsers;
+mod list_profile_roots;
+mod get_connector_config;
+mod update_connector_config;
+mod reinitialize;
+mod reload_config;
+mod get_diagnostics;
+mod dismiss_diagnostic;
 
 use crate::state::AppState;
 use crate::native_req::NativeMessage;
@@ -25,6 +40,21 @@ use crate::cmd::add_avatars::process_cmd_add_avatars;
 use crate::cmd::delete_avatar::process_cmd_delete_avatar;
 use crate::cmd::get_avatar::process_cmd_get_avatar;
 use crate::cmd::update_profiles_order::process_cmd_update_profiles_order;
+use crate::cmd::list_running_profiles::process_cmd_list_running_profiles;
+use crate::cmd::open_url::process_cmd_open_url;
+use crate::cmd::get_routing_rules::process_cmd_get_routing_rules;
+use crate::cmd::update_routing_rules::process_cmd_update_routing_rules;
+use crate::cmd::get_workspaces::process_cmd_get_workspaces;
+use crate::cmd::update_workspaces::process_cmd_update_workspaces;
+use crate::cmd::launch_workspace::process_cmd_launch_workspace;
+use crate::cmd::list_browsers::process_cmd_list_browsers;
+use crate::cmd::list_profile_roots::process_cmd_list_profile_roots;
+use crate::cmd::get_connector_config::process_cmd_get_connector_config;
+use crate::cmd::update_connector_config::process_cmd_update_connector_config;
+use crate::cmd::reinitialize::process_cmd_reinitialize;
+use crate::cmd::reload_config::process_cmd_reload_config;
+use crate::cmd::get_diagnostics::process_cmd_get_diagnostics;
+use crate::cmd::dismiss_diagnostic::process_cmd_dismiss_diagnostic;
 use crate::profiles::read_profiles;
 
 // === COMMANDS ===
@@ -50,7 +80,7 @@ pub fn execute_init_cmd(app_state: &mut AppState,
 
 pub fn execute_cmd_for_message(context: &AppContext,
                                msg: NativeMessage) -> NativeResponse {
-    let state = context.state;
+    let state = context.state();
     match msg {
         NativeMessage::Initialize(_) => NativeResponse::error("Connector cannot be initialized multiple times!"),
         NativeMessage::LaunchProfile(msg) => process_cmd_launch_profile(context, profiles!(state), msg),
@@ -62,6 +92,21 @@ pub fn execute_cmd_for_message(context: &AppContext,
         NativeMessage::AddAvatars => process_cmd_add_avatars(context, profiles!(state)),
         NativeMessage::GetAvatar(msg) => process_cmd_get_avatar(context, msg),
         NativeMessage::DeleteAvatar(msg) => process_cmd_delete_avatar(context, profiles!(state), msg),
-        NativeMessage::UpdateProfileOrder(msg) => process_cmd_update_profiles_order(context, profiles!(state), msg)
+        NativeMessage::UpdateProfileOrder(msg) => process_cmd_update_profiles_order(context, profiles!(state), msg),
+        NativeMessage::ListRunningProfiles => process_cmd_list_running_profiles(context, profiles!(state)),
+        NativeMessage::OpenUrl(msg) => process_cmd_open_url(context, profiles!(state), msg),
+        NativeMessage::GetRoutingRules => process_cmd_get_routing_rules(context),
+        NativeMessage::UpdateRoutingRules(msg) => process_cmd_update_routing_rules(context, profiles!(state), msg),
+        NativeMessage::GetWorkspaces => process_cmd_get_workspaces(context),
+        NativeMessage::UpdateWorkspaces(msg) => process_cmd_update_workspaces(context, profiles!(state), msg),
+        NativeMessage::LaunchWorkspace(msg) => process_cmd_launch_workspace(context, msg),
+        NativeMessage::ListBrowsers => process_cmd_list_browsers(),
+        NativeMessage::ListProfileRoots => process_cmd_list_profile_roots(context),
+        NativeMessage::GetConnectorConfig => process_cmd_get_connector_config(context),
+        NativeMessage::UpdateConnectorConfig(msg) => process_cmd_update_connector_config(context, profiles!(state), msg),
+        NativeMessage::Reinitialize(msg) => process_cmd_reinitialize(context, msg),
+        NativeMessage::ReloadConfig => process_cmd_reload_config(context),
+        NativeMessage::GetDiagnostics => process_cmd_get_diagnostics(context),
+        NativeMessage::DismissDiagnostic(msg) => process_cmd_dismiss_diagnostic(context, msg)
     }
 }
\ No newline at end of file
@@ -0,0 +1,28 @@
+use crate::AppContext;
+use crate::profiles::ProfilesIniState;
+use crate::native_req::{NativeMessageLaunchProfile, NativeMessageOpenUrl};
+use crate::native_resp::{NativeResponse, NativeResponseData};
+use crate::cmd::launch_profile::process_cmd_launch_profile;
+use crate::routing::RoutingData;
+
+pub fn process_cmd_open_url(context: &AppContext,
+                            profiles: ProfilesIniState,
+                            msg: NativeMessageOpenUrl) -> NativeResponse {
+    let routing_data = RoutingData::read(&context.state().config_dir);
+    let profile_id = match routing_data.route(&profiles, &msg.url) {
+        Some(p) => p.id.clone(),
+        None => return NativeResponse::success(NativeResponseData::UrlOpened { profile_id: None })
+    };
+
+    log::trace!("URL {} routed to profile: {}", msg.url, profile_id);
+
+    // Focus or launch the profile the same way the manager does
+    match process_cmd_launch_profile(context, profiles, NativeMessageLaunchProfile {
+        profile_id: profile_id.clone(),
+        url: Some(msg.url),
+        new_window: false
+    }) {
+        NativeResponse::Success { .. } => NativeResponse::success(NativeResponseData::UrlOpened { profile_id: Some(profile_id) }),
+        error => error
+    }
+}
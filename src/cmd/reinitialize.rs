@@ -0,0 +1,36 @@
+use crate::{AppContext, native_notify_app_state};
+use crate::cmd::initialize::process_cmd_initialize;
+use crate::config::{native_notify_updated_connector_config, read_configuration};
+use crate::ipc::rebind_ipc;
+use crate::native_req::NativeMessageInitialize;
+use crate::native_resp::NativeResponse;
+use crate::profiles::read_profiles;
+use crate::state::AppState;
+use crate::storage::connector_config_path;
+
+pub fn process_cmd_reinitialize(context: &AppContext,
+                                msg: NativeMessageInitialize) -> NativeResponse {
+    // Build the new state on the side so a failed attempt leaves the current one in place
+    let mut app_state = AppState::clone(&context.state());
+    app_state.config = read_configuration(&connector_config_path(&app_state.config_dir));
+
+    let profiles = match read_profiles(&app_state.config, &app_state.config_dir) {
+        Ok(p) => p,
+        Err(e) => return NativeResponse::error_with_dbg_msg("Failed to load profile list.", e)
+    };
+
+    let response = process_cmd_initialize(&mut app_state, profiles, msg);
+    if let NativeResponse::Success { .. } = response {
+        log::trace!("Connector reinitialized, new application state: {:?}", &app_state);
+        context.replace_state(app_state);
+
+        // Other instances must be able to reach us under the re-detected profile
+        if let Err(e) = rebind_ipc(context) {
+            log::error!("Failed to rebind IPC server: {:?}", e);
+        }
+
+        native_notify_updated_connector_config(&context.state());
+        native_notify_app_state(context);
+    }
+    response
+}
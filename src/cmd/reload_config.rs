@@ -0,0 +1,19 @@
+use crate::{AppContext, native_notify_app_state};
+use crate::config::{native_notify_updated_connector_config, reload_configuration};
+use crate::ipc::native_notify_profile_list;
+use crate::native_resp::{NativeResponse, NativeResponseConnectorConfig, NativeResponseData};
+use crate::options::native_notify_updated_options;
+
+pub fn process_cmd_reload_config(context: &AppContext) -> NativeResponse {
+    reload_configuration(context);
+
+    let app_state = context.state();
+    native_notify_updated_connector_config(&app_state);
+    native_notify_profile_list(&app_state);
+    native_notify_updated_options(&app_state);
+    native_notify_app_state(context);
+
+    NativeResponse::success(NativeResponseData::ConnectorConfig {
+        config: NativeResponseConnectorConfig::from_config(&app_state.config)
+    })
+}
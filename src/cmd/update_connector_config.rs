@@ -0,0 +1,62 @@
+use std::path::Path;
+use crate::AppContext;
+use crate::config::{apply_configuration, native_notify_updated_connector_config, read_configuration, write_configuration};
+use crate::ipc::{native_notify_profile_list, notify_update_connector_config};
+use crate::profiles::ProfilesIniState;
+use crate::native_req::NativeMessageUpdateConnectorConfig;
+use crate::native_resp::{NativeResponse, NativeResponseConnectorConfig, NativeResponseData};
+use crate::storage::{connector_config_path, portable_dir, resolve_config_path};
+
+pub fn process_cmd_update_connector_config(context: &AppContext,
+                                           profiles: ProfilesIniState,
+                                           msg: NativeMessageUpdateConnectorConfig) -> NativeResponse {
+    // Relative paths are only allowed in portable mode, they are relative to the executable
+    for path in msg.browser_profile_dir.iter().chain(msg.browser_binary.iter()) {
+        if !path.is_absolute() && portable_dir().is_none() {
+            return NativeResponse::error(format!("{} must be an absolute path!", path.display()));
+        }
+    }
+    if let Some(dir) = &msg.browser_profile_dir {
+        if !resolve_config_path(dir).join("profiles.ini").is_file() {
+            return NativeResponse::error(format!("{} does not contain a profiles.ini file!", dir.display()));
+        }
+    }
+    if let Some(binary) = &msg.browser_binary {
+        if !is_executable(&resolve_config_path(binary)) {
+            return NativeResponse::error(format!("{} is not an executable!", binary.display()));
+        }
+    }
+
+    // The config may have been edited by hand since we read it, only change what we were asked to
+    let config_path = connector_config_path(&context.state().config_dir);
+    let mut config = read_configuration(&config_path);
+    config.set_browser_profile_dir(msg.browser_profile_dir);
+    config.set_browser_binary(msg.browser_binary);
+
+    if let Err(e) = write_configuration(&config_path, &config) {
+        return NativeResponse::error_with_dbg_msg("Could not save connector configuration.", e);
+    }
+
+    apply_configuration(context, config);
+    // The other instances know the old profile list
+    notify_update_connector_config(context, &profiles);
+    native_notify_updated_connector_config(&context.state());
+    native_notify_profile_list(&context.state());
+
+    NativeResponse::success(NativeResponseData::ConnectorConfig {
+        config: NativeResponseConnectorConfig::from_config(&context.state().config)
+    })
+}
+
+fn is_executable(path: &Path) -> bool {
+    cfg_if::cfg_if! {
+        if #[cfg(target_family = "unix")] {
+            use std::os::unix::fs::PermissionsExt;
+            path.metadata()
+                .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+                .unwrap_or(false)
+        } else {
+            path.is_file() && path.extension().map_or(false, |e| e.eq_ignore_ascii_case("exe"))
+        }
+    }
+}
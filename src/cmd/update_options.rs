@@ -2,25 +2,24 @@ use crate::AppContext;
 use crate::profiles::ProfilesIniState;
 use crate::native_req::NativeMessageUpdateOptions;
 use crate::native_resp::{NativeResponse, NativeResponseData};
-use crate::storage::global_options_data_path;
-use crate::options::{read_global_options, write_global_options};
+use crate::metadata::update_metadata;
 use crate::ipc::notify_options_changed;
 
 pub fn process_cmd_update_options(context: &AppContext,
                               profiles: ProfilesIniState,
                               msg: NativeMessageUpdateOptions) -> NativeResponse {
-    let options_data_path = global_options_data_path(&context.state.config_dir);
-    let mut options = read_global_options(&options_data_path);
+    let result = update_metadata(&context.state().config_dir, |metadata| {
+        for change in msg.changes {
+            metadata.global_options.insert(change.0, change.1);
+        }
+        Ok(metadata.global_options.clone())
+    });
 
-    for change in msg.changes {
-        options.insert(change.0, change.1);
-    }
-
-    if let Err(e) = write_global_options(&options_data_path, &options) {
-        return NativeResponse::error_with_dbg_msg("Failed to save new changes!", e);
-    }
+    let options = match result {
+        Ok(o) => o,
+        Err(e) => return NativeResponse::error_with_dbg_msg("Failed to save new changes!", e)
+    };
     notify_options_changed(context, &profiles);
 
     return NativeResponse::success(NativeResponseData::OptionsUpdated { options })
 }
-
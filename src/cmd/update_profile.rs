@@ -27,23 +27,19 @@ pub fn process_cmd_update_profile(context: &AppContext, mut profiles: ProfilesIn
         profile.default = true
     }
 
-    let resp = NativeResponseProfileListProfileEntry {
-        id: msg.profile_id.clone(),
-        name: profile.name.clone(),
-        default: profile.default,
-        avatar: profile.avatar.clone(),
-        options: profile.options.clone()
-    };
+    let resp = NativeResponseProfileListProfileEntry::from_profile_entry(profile);
 
+    // Every profile root has its own default profile
     if msg.default {
+        let root_id = profile.root_id.clone();
         for profile in profiles.profile_entries.iter_mut() {
-            if profile.id != msg.profile_id {
+            if profile.id != msg.profile_id && profile.root_id == root_id {
                 profile.default = false
             }
         }
     }
 
-    if let Err(e) = write_profiles(&context.state.config, &context.state.config_dir, &profiles) {
+    if let Err(e) = write_profiles(&context.state().config, &context.state().config_dir, &profiles) {
         return NativeResponse::error_with_dbg_msg("Failed to save new changes!", e);
     }
     notify_profile_changed(context, &profiles);
@@ -19,7 +19,7 @@ pub fn process_cmd_update_profiles_order(context: &AppContext,
             return NativeResponse::error("Attempted to re-arrange profile that does not exist!");
         }
     }
-    if let Err(e) = new_order_data.write(&context.state.config_dir) {
+    if let Err(e) = new_order_data.write(&context.state().config_dir) {
         return NativeResponse::error_with_dbg_msg("Could not save profile order.", e);
     }
 
@@ -0,0 +1,42 @@
+use std::collections::HashSet;
+use crate::AppContext;
+use crate::profiles::ProfilesIniState;
+use crate::native_req::NativeMessageUpdateRoutingRules;
+use crate::native_resp::{NativeResponse, NativeResponseData};
+use crate::routing::RoutingData;
+
+pub fn process_cmd_update_routing_rules(context: &AppContext,
+                                        profiles: ProfilesIniState,
+                                        msg: NativeMessageUpdateRoutingRules) -> NativeResponse {
+    let mut rule_ids = HashSet::new();
+    for rule in &msg.rules {
+        if !rule_ids.insert(rule.id.as_str()) {
+            return NativeResponse::error("Multiple routing rules have the same ID!");
+        }
+        if !profiles.profile_entries.iter().any(|p| p.id == rule.profile_id) {
+            return NativeResponse::error("Attempted to route URLs to a profile that does not exist!");
+        }
+        if let Err(e) = rule.pattern.validate() {
+            return NativeResponse::error(e);
+        }
+    }
+
+    if let Some(fallback_profile_id) = &msg.fallback_profile_id {
+        if !profiles.profile_entries.iter().any(|p| &p.id == fallback_profile_id) {
+            return NativeResponse::error("Attempted to route URLs to a profile that does not exist!");
+        }
+    }
+
+    let routing_data = RoutingData {
+        rules: msg.rules,
+        fallback_profile_id: msg.fallback_profile_id
+    };
+    if let Err(e) = routing_data.write_and_notify(context, &profiles) {
+        return NativeResponse::error_with_dbg_msg("Could not save routing rules.", e);
+    }
+
+    NativeResponse::success(NativeResponseData::RoutingRules {
+        rules: routing_data.rules,
+        fallback_profile_id: routing_data.fallback_profile_id
+    })
+}
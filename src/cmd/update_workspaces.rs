@@ -0,0 +1,35 @@
+use std::collections::HashSet;
+use crate::AppContext;
+use crate::profiles::ProfilesIniState;
+use crate::native_req::NativeMessageUpdateWorkspaces;
+use crate::native_resp::{NativeResponse, NativeResponseData};
+use crate::workspaces::WorkspaceData;
+
+pub fn process_cmd_update_workspaces(context: &AppContext,
+                                     profiles: ProfilesIniState,
+                                     msg: NativeMessageUpdateWorkspaces) -> NativeResponse {
+    let mut workspace_ids = HashSet::new();
+    for workspace in &msg.workspaces {
+        if !workspace_ids.insert(workspace.id.as_str()) {
+            return NativeResponse::error("Multiple workspaces have the same ID!");
+        }
+        if workspace.name.trim().is_empty() {
+            return NativeResponse::error("Workspaces must have a name.");
+        }
+        for member in &workspace.members {
+            if !profiles.profile_entries.iter().any(|p| p.id == member.profile_id) {
+                return NativeResponse::error("Attempted to add a profile that does not exist to a workspace!");
+            }
+            if let Some(url) = member.urls.iter().find(|u| url::Url::parse(u).is_err()) {
+                return NativeResponse::error(format!("Invalid URL in workspace {}: {}", workspace.name, url));
+            }
+        }
+    }
+
+    let workspace_data = WorkspaceData { workspaces: msg.workspaces };
+    if let Err(e) = workspace_data.write_and_notify(context, &profiles) {
+        return NativeResponse::error_with_dbg_msg("Could not save workspaces.", e);
+    }
+
+    NativeResponse::success(NativeResponseData::Workspaces { workspaces: workspace_data.workspaces })
+}
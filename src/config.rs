@@ -1,34 +1,105 @@
 // === CONFIG ===
 
-use std::path::{PathBuf};
+use std::path::{Path, PathBuf};
 use serde::{Deserialize, Serialize};
 use cfg_if::cfg_if;
 use std::fs::OpenOptions;
-use once_cell::sync::Lazy;
+use once_cell::sync::{Lazy, OnceCell};
 use std::fs;
+use eyre::Context;
+use crate::diagnostics::{check_store_writable, read_store};
+use crate::native_resp::{write_native_event, NativeResponseConnectorConfig, NativeResponseEvent};
+use crate::state::{AppContext, AppState};
+use crate::storage::{connector_config_path, resolve_config_path};
+use crate::profile_root::{resolve_profile_root_from_parent, ProfileRootResolution, ProfileRootSource};
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Config {
     browser_profile_dir: Option<PathBuf>,
-    browser_binary: Option<PathBuf>
+    browser_binary: Option<PathBuf>,
+    // Profiles of other browsers (e.g. LibreWolf next to Firefox) to manage alongside our own
+    #[serde(default)]
+    extra_profile_roots: Vec<ProfileRootConfig>
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ProfileRootConfig {
+    // Short name qualifying the IDs of the profiles in this root, e.g. "librewolf"
+    pub id: String,
+    // Name of the browser shown to the user, defaults to the ID
+    pub name: Option<String>,
+    pub profile_dir: PathBuf,
+    // Found among the installed browsers if missing
+    pub browser_binary: Option<PathBuf>
 }
 
 impl Config {
     pub fn browser_profile_dir(&self) -> PathBuf {
-        self.browser_profile_dir.clone()
-            .unwrap_or_else(|| get_default_browser_profile_folder().clone())
+        PROFILE_ROOT_OVERRIDE.get()
+            .cloned()
+            .or_else(|| self.browser_profile_dir.as_deref().map(resolve_config_path))
+            .unwrap_or_else(|| get_default_browser_profile_folder().root.clone())
+    }
+    pub fn profile_root_resolution(&self) -> ProfileRootResolution {
+        if let Some(dir) = PROFILE_ROOT_OVERRIDE.get() {
+            return ProfileRootResolution {
+                root: dir.clone(),
+                source: ProfileRootSource::Override,
+                steps: vec![format!("Using the profile root from the command line or environment: {:?}", dir)]
+            };
+        }
+        match &self.browser_profile_dir {
+            Some(dir) => ProfileRootResolution {
+                root: resolve_config_path(dir),
+                source: ProfileRootSource::Configured,
+                steps: vec![format!("Using browser_profile_dir from the connector config: {:?}", dir)]
+            },
+            None => get_default_browser_profile_folder().clone()
+        }
     }
-    pub fn browser_binary(&self) -> Option<&PathBuf> {
+    pub fn browser_binary(&self) -> Option<PathBuf> {
+        self.browser_binary.as_deref().map(resolve_config_path)
+    }
+    // As configured, relative paths are not resolved
+    pub fn configured_browser_binary(&self) -> Option<&PathBuf> {
         self.browser_binary.as_ref()
     }
+    // The configured profile dir, None if it is detected automatically
+    pub fn configured_browser_profile_dir(&self) -> Option<&PathBuf> {
+        self.browser_profile_dir.as_ref()
+    }
+    pub fn set_browser_profile_dir(&mut self, dir: Option<PathBuf>) {
+        self.browser_profile_dir = dir;
+    }
+    pub fn set_browser_binary(&mut self, binary: Option<PathBuf>) {
+        self.browser_binary = binary;
+    }
 
-    pub fn profiles_ini_path(&self) -> PathBuf {
-        let mut profiles_ini = self.browser_profile_dir();
+    pub fn extra_profile_roots(&self) -> &[ProfileRootConfig] {
+        &self.extra_profile_roots
+    }
+    pub fn extra_profile_root(&self, root_id: &str) -> Option<&ProfileRootConfig> {
+        self.extra_profile_roots.iter().find(|r| r.id == root_id)
+    }
+
+    // The primary root (the one of the browser that launched us) has no ID
+    pub fn profile_root_dir(&self, root_id: Option<&str>) -> PathBuf {
+        match root_id.map(|id| (id, self.extra_profile_root(id))) {
+            None => self.browser_profile_dir(),
+            Some((_, Some(root))) => resolve_config_path(&root.profile_dir),
+            Some((id, None)) => {
+                log::warn!("Unknown profile root {}, using the primary root instead", id);
+                self.browser_profile_dir()
+            }
+        }
+    }
+    pub fn profiles_ini_path(&self, root_id: Option<&str>) -> PathBuf {
+        let mut profiles_ini = self.profile_root_dir(root_id);
         profiles_ini.push("profiles.ini");
         return profiles_ini;
     }
-    pub fn installs_ini_path(&self) -> PathBuf {
-        let mut installs_ini = self.browser_profile_dir();
+    pub fn installs_ini_path(&self, root_id: Option<&str>) -> PathBuf {
+        let mut installs_ini = self.profile_root_dir(root_id);
         installs_ini.push("installs.ini");
         return installs_ini;
     }
@@ -71,6 +142,15 @@ pub fn get_msix_package() -> Result<&'static String, &'static String> {
     MSIX_PACKAGE.as_ref()
 }
 
+// Set from the command line or the environment, takes precedence over the config
+static PROFILE_ROOT_OVERRIDE: OnceCell<PathBuf> = OnceCell::new();
+
+pub fn set_profile_root_override(dir: PathBuf) {
+    if PROFILE_ROOT_OVERRIDE.set(dir).is_err() {
+        log::warn!("Profile root override was already set, ignoring");
+    }
+}
+
 // Define Firefox fork directory names
 const FIREFOX_DIRS: [&str; 4] = ["firefox", "librewolf", "waterfox", "zen-browser"];
 
@@ -88,7 +168,21 @@ const FLATPAK_APP_IDS: [(&str, &str); 4] = [
     ("zen-browser", "org.mozilla.firefox.zen")  // Adjust if Zen has a different Flatpak ID
 ];
 
-static DEFAULT_BROWSER_PROFILE_FOLDER: Lazy<PathBuf> = Lazy::new(|| {
+static DEFAULT_BROWSER_PROFILE_FOLDER: Lazy<ProfileRootResolution> = Lazy::new(|| {
+    let mut steps = Vec::new();
+    if let Some(root) = resolve_profile_root_from_parent(&mut steps) {
+        return ProfileRootResolution { root, source: ProfileRootSource::ParentBrowser, steps };
+    }
+
+    let root = find_known_browser_profile_folder();
+    let step = format!("Falling back to the first profile dir of a known browser: {:?}", root);
+    log::info!("Profile root resolution: {}", step);
+    steps.push(step);
+    ProfileRootResolution { root, source: ProfileRootSource::Fallback, steps }
+});
+
+// The first profile dir of a known browser that has a profiles.ini
+fn find_known_browser_profile_folder() -> PathBuf {
     let user_dirs = directories::UserDirs::new()
         .expect("Unable to determine user folder!");
 
@@ -196,9 +290,9 @@ static DEFAULT_BROWSER_PROFILE_FOLDER: Lazy<PathBuf> = Lazy::new(|| {
     
     log::trace!("Found default browser profile dir: {:?}", result);
     return result;
-});
+}
 
-fn get_default_browser_profile_folder() -> &'static PathBuf {
+fn get_default_browser_profile_folder() -> &'static ProfileRootResolution {
     &DEFAULT_BROWSER_PROFILE_FOLDER
 }
 
@@ -206,18 +300,53 @@ impl Default for Config {
     fn default() -> Self {
         Config {
             browser_profile_dir: None,
-            browser_binary: None
+            browser_binary: None,
+            extra_profile_roots: Vec::new()
         }
     }
 }
 
-pub fn read_configuration(path: &PathBuf) -> Config {
-    if let Ok(file) = OpenOptions::new().read(true).open(path) {
-        if let Ok(config) = serde_json::from_reader(file) {
-            return config;
-        }
-    }
-
+pub fn read_configuration(path: &Path) -> Config {
     // Config doesn't exist or is invalid, load default config
-    Config::default()
-}
\ No newline at end of file
+    read_store(path, "connector config")
+}
+
+/// Write the configuration to a temporary file first and move it into place, so a crash never
+/// leaves a half written config behind.
+pub fn write_configuration(path: &Path, config: &Config) -> eyre::Result<()> {
+    check_store_writable(path)?;
+
+    let tmp_path = path.with_extension("json.tmp");
+    let tmp_file = OpenOptions::new()
+        .create(true)
+        .truncate(true)
+        .write(true)
+        .open(&tmp_path)
+        .context("failed to open temporary config file for writing")?;
+
+    serde_json::to_writer_pretty(&tmp_file, config)
+        .context("failed to write config to file")?;
+    tmp_file.sync_all()
+        .context("failed to flush config file")?;
+
+    fs::rename(&tmp_path, path)
+        .context("failed to replace config file")
+}
+
+/// Use the new configuration from now on, without restarting the connector.
+pub fn apply_configuration(context: &AppContext, config: Config) {
+    context.update_state(|app_state| app_state.config = config.clone());
+}
+
+/// Read the configuration from disk again, e.g. after another instance changed it.
+pub fn reload_configuration(context: &AppContext) {
+    let config = read_configuration(&connector_config_path(&context.state().config_dir));
+    log::trace!("Configuration reloaded: {:?}", &config);
+    apply_configuration(context, config);
+}
+
+pub fn native_notify_updated_connector_config(app_state: &AppState) {
+    write_native_event(NativeResponseEvent::ConnectorConfigUpdated {
+        config: NativeResponseConnectorConfig::from_config(&app_state.config)
+    });
+}
@@ -0,0 +1,272 @@
+use cfg_if::cfg_if;
+
+cfg_if! {
+    if #[cfg(target_os = "linux")] {
+        use std::{env, fs};
+        use std::collections::HashSet;
+        use std::path::{Path, PathBuf};
+        use ini::{Ini, ParseOption};
+    }
+}
+
+// === XDG DESKTOP ENTRIES ===
+
+// Desktop entries have their own escaping rules, handle them ourselves
+#[cfg(target_os = "linux")]
+const DESKTOP_ENTRY_PARSE_OPTION: ParseOption = ParseOption {
+    enabled_quote: false,
+    enabled_escape: false
+};
+
+#[cfg(target_os = "linux")]
+const DESKTOP_ENTRY_SECTION: &str = "Desktop Entry";
+
+#[cfg(target_os = "linux")]
+#[derive(Clone, Debug)]
+pub struct DesktopEntry {
+    pub path: PathBuf,
+    pub name: String,
+    pub exec: LaunchTemplate,
+    pub startup_wm_class: Option<String>,
+    // Set by Flatpak on the entries it exports
+    pub flatpak_app_id: Option<String>
+}
+
+// A command line from a desktop entry, the browser arguments are inserted where the file/URL
+// field code used to be
+#[derive(Clone, Debug, PartialEq)]
+pub struct LaunchTemplate {
+    pub program: String,
+    pub args_before: Vec<String>,
+    pub args_after: Vec<String>
+}
+
+impl LaunchTemplate {
+    /// Parse the Exec key of a desktop entry.
+    #[cfg(target_os = "linux")]
+    pub fn parse_exec(exec: &str) -> Option<LaunchTemplate> {
+        let mut program = None::<String>;
+        let mut args_before = Vec::new();
+        let mut args_after = Vec::new();
+        let mut found_insert_point = false;
+
+        for arg in split_exec(exec)? {
+            match arg.as_str() {
+                "%f" | "%F" | "%u" | "%U" => {
+                    found_insert_point = true;
+                    continue;
+                }
+                // Flatpak would treat our arguments as files to forward into the sandbox
+                "--file-forwarding" | "@@" | "@@u" => continue,
+                _ => {}
+            }
+            let arg = match expand_field_codes(&arg) {
+                Some(a) => a,
+                None => continue
+            };
+            if program.is_none() {
+                program = Some(arg);
+            } else if found_insert_point {
+                args_after.push(arg);
+            } else {
+                args_before.push(arg);
+            }
+        }
+
+        Some(LaunchTemplate { program: program?, args_before, args_after })
+    }
+
+    /// Build the full argument list for the program.
+    pub fn expand(&self, args: Vec<String>) -> Vec<String> {
+        self.args_before.iter().cloned()
+            .chain(args)
+            .chain(self.args_after.iter().cloned())
+            .collect()
+    }
+}
+
+// Replace the field codes in a single argument, returns None if the argument consisted only of a field code
+#[cfg(target_os = "linux")]
+fn expand_field_codes(arg: &str) -> Option<String> {
+    let mut result = String::with_capacity(arg.len());
+    let mut chars = arg.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('%') => result.push('%'),
+            // Everything else (icon, name, location and deprecated codes) is not useful to us
+            Some(_) | None => {}
+        }
+    }
+    if result.is_empty() && !arg.is_empty() {
+        None
+    } else {
+        Some(result)
+    }
+}
+
+// Split an Exec value into arguments, following the quoting rules of the desktop entry spec
+#[cfg(target_os = "linux")]
+fn split_exec(exec: &str) -> Option<Vec<String>> {
+    let mut args = Vec::new();
+    let mut cur = None::<String>;
+    let mut quoted = false;
+    let mut chars = exec.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                cur.get_or_insert_with(String::new);
+            }
+            '\\' if quoted => cur.get_or_insert_with(String::new).push(chars.next()?),
+            c if c.is_whitespace() && !quoted => {
+                if let Some(arg) = cur.take() {
+                    args.push(arg);
+                }
+            }
+            c => cur.get_or_insert_with(String::new).push(c)
+        }
+    }
+    // Unterminated quote
+    if quoted {
+        return None;
+    }
+    if let Some(arg) = cur {
+        args.push(arg);
+    }
+    Some(args)
+}
+
+// Undo the escaping of string values (\s, \n, \t, \r and \\)
+#[cfg(target_os = "linux")]
+fn unescape_value(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('s') => result.push(' '),
+            Some('n') => result.push('\n'),
+            Some('t') => result.push('\t'),
+            Some('r') => result.push('\r'),
+            Some('\\') => result.push('\\'),
+            Some(c) => {
+                result.push('\\');
+                result.push(c);
+            }
+            None => result.push('\\')
+        }
+    }
+    result
+}
+
+#[cfg(target_os = "linux")]
+impl DesktopEntry {
+    pub fn read(path: &Path) -> Option<DesktopEntry> {
+        let ini = match Ini::load_from_file_opt(path, DESKTOP_ENTRY_PARSE_OPTION) {
+            Ok(ini) => ini,
+            Err(e) => {
+                log::trace!("Skipping unreadable desktop entry {:?}: {:?}", path, e);
+                return None;
+            }
+        };
+        let section = ini.section(Some(DESKTOP_ENTRY_SECTION))?;
+        if section.get("Type") != Some("Application") || section.get("Hidden") == Some("true") {
+            return None;
+        }
+
+        let exec = unescape_value(section.get("Exec")?);
+        let exec = match LaunchTemplate::parse_exec(&exec) {
+            Some(e) => e,
+            None => {
+                log::warn!("Desktop entry {:?} has an invalid Exec key: {:?}", path, exec);
+                return None;
+            }
+        };
+
+        Some(DesktopEntry {
+            path: path.to_path_buf(),
+            name: unescape_value(section.get("Name")?),
+            exec,
+            startup_wm_class: section.get("StartupWMClass").map(unescape_value),
+            flatpak_app_id: section.get("X-Flatpak").map(unescape_value)
+        })
+    }
+}
+
+// Directories to look for desktop entries in, most important first
+#[cfg(target_os = "linux")]
+fn application_dirs() -> Vec<PathBuf> {
+    let data_home = env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .or_else(|| directories::BaseDirs::new().map(|d| d.home_dir().join(".local").join("share")));
+    let data_dirs = env::var("XDG_DATA_DIRS")
+        .ok()
+        .filter(|d| !d.is_empty())
+        .unwrap_or_else(|| "/usr/local/share:/usr/share".to_owned());
+
+    data_home.into_iter()
+        .chain(data_dirs.split(':').map(PathBuf::from).filter(|p| p.is_absolute()))
+        .map(|p| p.join("applications"))
+        .collect()
+}
+
+/// Read all desktop entries the user can see. Entries in more important directories hide the
+/// entries with the same ID in the less important ones.
+#[cfg(target_os = "linux")]
+pub fn read_desktop_entries() -> Vec<DesktopEntry> {
+    let mut seen_ids = HashSet::new();
+    let mut entries = Vec::new();
+    for dir in application_dirs() {
+        for path in list_desktop_files(&dir) {
+            // The ID of an entry is its path relative to the applications dir with '/' replaced by '-'
+            let id = path.strip_prefix(&dir)
+                .map(|p| p.to_string_lossy().replace('/', "-"))
+                .unwrap_or_default();
+            if !seen_ids.insert(id) {
+                continue;
+            }
+            if let Some(entry) = DesktopEntry::read(&path) {
+                entries.push(entry);
+            }
+        }
+    }
+    entries
+}
+
+#[cfg(target_os = "linux")]
+fn list_desktop_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let mut entries: Vec<PathBuf> = match fs::read_dir(dir) {
+        Ok(entries) => entries.filter_map(|e| e.ok()).map(|e| e.path()).collect(),
+        Err(_) => return files
+    };
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            files.extend(list_desktop_files(&path));
+        } else if path.extension().map_or(false, |e| e == "desktop") {
+            files.push(path);
+        }
+    }
+    files
+}
+
+// Find a program in PATH like a shell would
+#[cfg(target_os = "linux")]
+pub fn resolve_program(program: &str) -> Option<PathBuf> {
+    let program_path = Path::new(program);
+    if program_path.is_absolute() {
+        return Some(program_path.to_path_buf()).filter(|p| p.is_file());
+    }
+    env::split_paths(&env::var_os("PATH")?)
+        .map(|dir| dir.join(program))
+        .find(|p| p.is_file())
+}
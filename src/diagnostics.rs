@@ -0,0 +1,170 @@
+use std::fs;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use eyre::eyre;
+use serde::{Serialize, Deserialize};
+use serde::de::DeserializeOwned;
+use crate::native_resp::{NativeResponseEvent, write_native_event};
+
+// === STORE DIAGNOSTICS ===
+
+// Suffix of the marker that sits next to a quarantined store until the problem is resolved
+const DIAGNOSTIC_MARKER_SUFFIX: &str = ".diagnostic";
+
+// Events can only be sent when the browser started us, not from the command line
+static NATIVE_EVENTS_ENABLED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct StoreDiagnostic {
+    // File name of the store, e.g. "config.json"
+    pub store: String,
+    pub error: String,
+    // Where the corrupt file was moved to
+    pub quarantined_to: Option<PathBuf>,
+    pub time: String
+}
+
+pub fn enable_native_diagnostics_events() {
+    NATIVE_EVENTS_ENABLED.store(true, Ordering::SeqCst);
+}
+
+fn diagnostic_marker_path(path: &Path) -> PathBuf {
+    let mut marker = path.as_os_str().to_owned();
+    marker.push(DIAGNOSTIC_MARKER_SUFFIX);
+    PathBuf::from(marker)
+}
+
+fn store_name(path: &Path) -> String {
+    path.file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| path.to_string_lossy().to_string())
+}
+
+/// Read a JSON store, falling back to defaults if it does not exist. A store that can't be parsed
+/// is moved aside and writes to it are refused until the problem is resolved.
+pub fn read_store<T: DeserializeOwned + Default>(path: &Path, description: &str) -> T {
+    let file = match OpenOptions::new().read(true).open(path) {
+        Ok(f) => f,
+        Err(e) => {
+            if e.kind() != io::ErrorKind::NotFound {
+                log::warn!("Could not open {} file: {:?}, falling back to defaults", description, e);
+            }
+            return T::default();
+        }
+    };
+
+    match serde_json::from_reader(file) {
+        Ok(data) => {
+            // The user put back a fixed file
+            if diagnostic_marker_path(path).exists() {
+                resolve_diagnostic(path);
+            }
+            data
+        }
+        Err(e) => {
+            log::error!("{} file is incorrectly formatted: {:?}", description, e);
+            quarantine_store(path, format!("{} file is incorrectly formatted: {}", description, e));
+            T::default()
+        }
+    }
+}
+
+pub fn quarantine_store(path: &Path, error: String) {
+    let now = chrono::Local::now();
+    let mut quarantine_path = path.as_os_str().to_owned();
+    quarantine_path.push(format!(".corrupt-{}", now.format("%Y%m%d-%H%M%S")));
+    let quarantine_path = PathBuf::from(quarantine_path);
+
+    let quarantined_to = match fs::rename(path, &quarantine_path) {
+        Ok(()) => Some(quarantine_path),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            // Another instance read the same store and quarantined it first, its marker points to the data
+            log::info!("{:?} was already quarantined by another instance", path);
+            if let Some(dir) = path.parent() {
+                native_notify_diagnostics(dir);
+            }
+            return;
+        }
+        Err(e) => {
+            log::error!("Failed to quarantine {:?}: {:?}", path, e);
+            None
+        }
+    };
+
+    let diagnostic = StoreDiagnostic {
+        store: store_name(path),
+        error,
+        quarantined_to,
+        time: now.to_rfc3339()
+    };
+    // Keep the marker on disk so every instance refuses to overwrite the store. An existing marker
+    // is never replaced, it may be the only pointer to data quarantined earlier.
+    let result = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(diagnostic_marker_path(path))
+        .and_then(|mut file| file.write_all(&serde_json::to_vec(&diagnostic).unwrap()));
+    match result {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => log::info!("Keeping the existing diagnostic for {:?}", path),
+        Err(e) => log::error!("Failed to write diagnostic for {:?}: {:?}", path, e)
+    }
+
+    if let Some(dir) = path.parent() {
+        native_notify_diagnostics(dir);
+    }
+}
+
+/// Refuse to write a store that was quarantined, the write would replace the user's data with defaults.
+pub fn check_store_writable(path: &Path) -> eyre::Result<()> {
+    if diagnostic_marker_path(path).exists() {
+        return Err(eyre!("{} could not be read earlier and was quarantined, it must be fixed or dismissed before it can be changed", store_name(path)));
+    }
+    Ok(())
+}
+
+/// Stop refusing writes to the store, the user accepts losing the quarantined data.
+pub fn resolve_diagnostic(path: &Path) {
+    match fs::remove_file(diagnostic_marker_path(path)) {
+        Ok(()) => log::info!("Diagnostic for {:?} resolved", path),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return,
+        Err(e) => {
+            log::error!("Failed to remove diagnostic for {:?}: {:?}", path, e);
+            return;
+        }
+    }
+    if let Some(dir) = path.parent() {
+        native_notify_diagnostics(dir);
+    }
+}
+
+/// List the unresolved problems of the stores in the directory.
+pub fn read_diagnostics(dir: &Path) -> Vec<StoreDiagnostic> {
+    let entries = match fs::read_dir(dir) {
+        Ok(e) => e,
+        Err(_) => return Vec::new()
+    };
+    let mut diagnostics: Vec<StoreDiagnostic> = entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.to_string_lossy().ends_with(DIAGNOSTIC_MARKER_SUFFIX))
+        .filter_map(|p| fs::read(&p).ok())
+        .filter_map(|data| serde_json::from_slice(&data).ok())
+        .collect();
+    diagnostics.sort_by(|a, b| a.store.cmp(&b.store));
+    diagnostics
+}
+
+pub fn native_notify_diagnostics(dir: &Path) {
+    let diagnostics = read_diagnostics(dir);
+    if !NATIVE_EVENTS_ENABLED.load(Ordering::SeqCst) {
+        for diagnostic in &diagnostics {
+            eprintln!("Warning: {}", diagnostic.error);
+        }
+        return;
+    }
+    write_native_event(NativeResponseEvent::Diagnostics { diagnostics });
+}
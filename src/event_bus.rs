@@ -0,0 +1,223 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use nng::{Dialer, Listener, Message, Protocol, Socket};
+use nng::options::{Options, SendTimeout};
+use eyre::eyre;
+use once_cell::sync::Lazy;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Serialize, Deserialize};
+use crate::avatars::update_and_native_notify_avatars;
+use crate::instances::InstanceRecord;
+use crate::ipc::{get_event_bus_socket_name, load_ipc_key, native_notify_profile_list, sign_ipc_message, verify_ipc_message};
+use crate::options::native_notify_updated_options;
+use crate::profiles_order::native_notify_updated_profile_order;
+use crate::state::AppContext;
+
+// === EVENT BUS ===
+
+// Every instance listens on its own bus socket and dials the sockets of all other live instances,
+// so an event reaches every connector without a round-trip per profile
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum ConnectorEvent {
+    ProfileListChanged,
+    OptionsChanged,
+    AvatarsChanged,
+    OrderChanged
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct BusMessage {
+    // Random ID of the publishing instance, PIDs can be reused
+    origin: u64,
+    // Increases by one with every event the origin publishes, starting at 1
+    sequence: u64,
+    event: ConnectorEvent
+}
+
+struct EventBus {
+    socket: Socket,
+    socket_name: String,
+    origin: u64,
+    last_sequence: u64,
+    // Dialers to the bus sockets of the other instances
+    peers: HashMap<String, Dialer>
+}
+
+static EVENT_BUS: Lazy<Mutex<Option<EventBus>>> = Lazy::new(|| Mutex::new(None));
+
+/// Join the event bus and handle the events of the other instances in the background.
+pub fn setup_event_bus(context: &AppContext) -> eyre::Result<()> {
+    log::trace!("Starting event bus...");
+    let socket = Socket::new(Protocol::Bus0)?;
+    // Peers that don't keep up miss events instead of blocking the publisher
+    socket.set_opt::<SendTimeout>(Some(Duration::from_millis(500)))?;
+    let socket_name = get_event_bus_socket_name()?;
+    let listener = Listener::new(&socket, &socket_name)?;
+
+    let mut origin = [0u8; 8];
+    SystemRandom::new().fill(&mut origin)
+        .map_err(|_| eyre!("failed to generate event bus origin"))?;
+    *EVENT_BUS.lock().unwrap() = Some(EventBus {
+        socket: socket.clone(),
+        socket_name,
+        origin: u64::from_le_bytes(origin),
+        last_sequence: 0,
+        peers: HashMap::new()
+    });
+
+    let context = context.clone();
+    thread::spawn(move || {
+        // Last sequence number received from each origin
+        let mut received: HashMap<u64, u64> = HashMap::new();
+        loop {
+            match socket.recv() {
+                Ok(msg) => handle_bus_message(&context, &mut received, msg),
+                Err(e) => {
+                    log::error!("Event bus failed: {:?}", e);
+                    break;
+                }
+            }
+        }
+        listener.close();
+        *EVENT_BUS.lock().unwrap() = None;
+    });
+    Ok(())
+}
+
+/// Socket other instances can reach our event bus on, if we joined it.
+pub fn event_bus_socket_name() -> Option<String> {
+    EVENT_BUS.lock().unwrap().as_ref().map(|bus| bus.socket_name.clone())
+}
+
+/// Dial the buses of newly started instances and hang up on the ones that stopped.
+pub fn connect_event_bus_peers(instances: &[InstanceRecord]) {
+    let mut event_bus = EVENT_BUS.lock().unwrap();
+    let bus = match event_bus.as_mut() {
+        Some(b) => b,
+        None => return
+    };
+
+    let live: Vec<&String> = instances.iter()
+        .filter_map(|instance| instance.events_socket.as_ref())
+        .filter(|socket_name| **socket_name != bus.socket_name)
+        .collect();
+    let dead: Vec<String> = bus.peers.keys()
+        .filter(|socket_name| !live.contains(socket_name))
+        .cloned()
+        .collect();
+    for socket_name in dead {
+        log::trace!("Disconnecting from event bus peer: {}", socket_name);
+        if let Some(dialer) = bus.peers.remove(&socket_name) {
+            dialer.close();
+        }
+    }
+    for socket_name in live {
+        if bus.peers.contains_key(socket_name) {
+            continue;
+        }
+        log::trace!("Connecting to event bus peer: {}", socket_name);
+        // Non-blocking, the dialer keeps retrying in the background
+        match Dialer::new(&bus.socket, socket_name, true) {
+            Ok(dialer) => { bus.peers.insert(socket_name.clone(), dialer); }
+            Err(e) => log::warn!("Failed to connect to event bus peer {}: {:?}", socket_name, e)
+        }
+    }
+}
+
+/// Send the event to every other instance on the bus. Returns `false` if we are not on the bus or
+/// the event could not be sent.
+pub fn publish_event(context: &AppContext, event: ConnectorEvent) -> bool {
+    let key = match load_ipc_key(&context.state().config_dir) {
+        Ok(k) => k,
+        Err(e) => {
+            log::error!("Failed to load IPC secret, unable to publish event: {:?}", e);
+            return false
+        }
+    };
+
+    let mut event_bus = EVENT_BUS.lock().unwrap();
+    let bus = match event_bus.as_mut() {
+        Some(b) => b,
+        None => return false
+    };
+    bus.last_sequence += 1;
+    let message = BusMessage {
+        origin: bus.origin,
+        sequence: bus.last_sequence,
+        event
+    };
+    log::trace!("Publishing event: {:?}", message);
+    let serialized = match serde_cbor::to_vec(&message) {
+        Ok(s) => s,
+        Err(e) => {
+            log::error!("Failed to serialize event: {:?}", e);
+            return false
+        }
+    };
+    if let Err((_, e)) = bus.socket.send(Message::from(sign_ipc_message(&key, &serialized).as_slice())) {
+        log::warn!("Failed to publish event {:?}: {:?}", event, e);
+        // Nobody received it, so there is no gap for the peers to notice
+        bus.last_sequence -= 1;
+        return false
+    }
+    true
+}
+
+fn handle_bus_message(context: &AppContext, received: &mut HashMap<u64, u64>, msg: Message) {
+    let key = match load_ipc_key(&context.state().config_dir) {
+        Ok(k) => k,
+        Err(e) => {
+            log::error!("Failed to load IPC secret, dropping event: {:?}", e);
+            return
+        }
+    };
+    let payload = match verify_ipc_message(&key, msg.as_slice()) {
+        Some(p) => p,
+        None => {
+            log::warn!("Rejected unauthenticated event ({} bytes)", msg.len());
+            return
+        }
+    };
+    let message: BusMessage = match serde_cbor::from_slice(payload) {
+        Ok(m) => m,
+        Err(e) => {
+            log::error!("Failed to read event from bus: {:?}", e);
+            return
+        }
+    };
+    log::trace!("Received event: {:?}", message);
+
+    let last_sequence = match received.get(&message.origin) {
+        Some(sequence) => *sequence,
+        // The origin may have published events before we connected to it, those were never meant
+        // for us
+        None => message.sequence.saturating_sub(1)
+    };
+    if message.sequence <= last_sequence {
+        // Peers that dial each other get every event twice
+        return;
+    }
+    received.insert(message.origin, message.sequence);
+
+    if message.sequence > last_sequence + 1 {
+        log::warn!("Missed {} event(s) from event bus origin {:x}, refreshing everything",
+                   message.sequence - last_sequence - 1, message.origin);
+        for event in &[ConnectorEvent::ProfileListChanged, ConnectorEvent::OptionsChanged, ConnectorEvent::AvatarsChanged, ConnectorEvent::OrderChanged] {
+            handle_event(context, *event);
+        }
+    } else {
+        handle_event(context, message.event);
+    }
+}
+
+pub fn handle_event(context: &AppContext, event: ConnectorEvent) {
+    match event {
+        ConnectorEvent::ProfileListChanged => native_notify_profile_list(&context.state()),
+        ConnectorEvent::OptionsChanged => native_notify_updated_options(&context.state()),
+        ConnectorEvent::AvatarsChanged => update_and_native_notify_avatars(context),
+        ConnectorEvent::OrderChanged => native_notify_updated_profile_order(&context.state())
+    }
+}
@@ -0,0 +1,133 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use cfg_if::cfg_if;
+use serde::{Serialize, Deserialize};
+use crate::APP_VERSION;
+
+cfg_if! {
+    if #[cfg(target_family = "unix")] {
+        use nix::sys::signal::kill;
+        use nix::unistd::Pid;
+    }
+}
+
+// How often running instances refresh their record
+pub const INSTANCE_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+// Instances that missed a few heartbeats are considered dead
+const INSTANCE_STALE_AFTER: Duration = Duration::from_secs(45);
+
+// === INSTANCE REGISTRY ===
+
+// A running connector that can be reached over IPC. Every instance keeps a record named after its
+// PID in the registry directory.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct InstanceRecord {
+    pub profile_id: String,
+    pub pid: u32,
+    // IPC socket the instance listens on
+    pub socket: String,
+    // Socket of the instance's event bus, missing for connectors without one
+    #[serde(default)]
+    pub events_socket: Option<String>,
+    pub version: String,
+    // Seconds since the epoch
+    pub heartbeat: u64
+}
+
+impl InstanceRecord {
+    fn is_alive(&self) -> bool {
+        let heartbeat = UNIX_EPOCH + Duration::from_secs(self.heartbeat);
+        let fresh = SystemTime::now().duration_since(heartbeat)
+            .map(|age| age < INSTANCE_STALE_AFTER)
+            // The heartbeat is in the future, the clock changed
+            .unwrap_or(true);
+        fresh && is_process_alive(self.pid)
+    }
+}
+
+fn is_process_alive(pid: u32) -> bool {
+    cfg_if! {
+        if #[cfg(target_family = "unix")] {
+            kill(Pid::from_raw(pid as i32), None).is_ok()
+        } else {
+            // Only the heartbeat tells whether the instance is still running
+            let _ = pid;
+            true
+        }
+    }
+}
+
+fn instance_record_path(registry_dir: &Path, pid: u32) -> PathBuf {
+    registry_dir.join(format!("{}.json", pid))
+}
+
+/// Record that this instance is listening on `socket` for the profile, refreshing its heartbeat.
+pub fn register_instance(registry_dir: &Path, profile_id: &str, socket: &str, events_socket: Option<&str>) -> io::Result<()> {
+    let heartbeat = SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let record = InstanceRecord {
+        profile_id: profile_id.to_owned(),
+        pid: process::id(),
+        socket: socket.to_owned(),
+        events_socket: events_socket.map(str::to_owned),
+        version: APP_VERSION.to_owned(),
+        heartbeat
+    };
+
+    let record_path = instance_record_path(registry_dir, record.pid);
+    let mut tmp_path = record_path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+    fs::write(&tmp_path, serde_json::to_vec(&record)?)?;
+    // Other instances never see a partially written record
+    fs::rename(&tmp_path, &record_path)
+}
+
+/// List the instances that are still running, removing the records of the others.
+pub fn list_live_instances(registry_dir: &Path) -> io::Result<Vec<InstanceRecord>> {
+    let mut instances = Vec::new();
+    for entry in fs::read_dir(registry_dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if path.extension().map_or(false, |e| e == "tmp") {
+            prune_orphaned_record(&entry);
+            continue;
+        }
+        if path.extension().map_or(true, |e| e != "json") {
+            continue;
+        }
+        let record = fs::read(&path).ok()
+            .and_then(|data| serde_json::from_slice::<InstanceRecord>(&data).ok());
+        match record {
+            Some(record) if record.is_alive() => instances.push(record),
+            _ => {
+                log::trace!("Pruning dead instance record: {:?}", path);
+                if let Err(e) = fs::remove_file(&path) {
+                    log::warn!("Failed to prune instance record {:?}: {:?}", path, e);
+                }
+            }
+        }
+    }
+    Ok(instances)
+}
+
+// Records that were never renamed into place are left behind by instances that died while writing
+// them. Recent ones may still be in the middle of being written.
+fn prune_orphaned_record(entry: &fs::DirEntry) {
+    let orphaned = entry.metadata()
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+        .map_or(false, |age| age >= INSTANCE_STALE_AFTER);
+    if orphaned {
+        let path = entry.path();
+        log::trace!("Pruning orphaned instance record: {:?}", path);
+        if let Err(e) = fs::remove_file(&path) {
+            log::warn!("Failed to prune instance record {:?}: {:?}", path, e);
+        }
+    }
+}
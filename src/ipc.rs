@@ -1,22 +1,51 @@
-use std::{io, thread};
-use crate::state::AppState;
-use std::time::Duration;
-use crate::native_resp::{NativeResponseEvent, NativeResponseProfileListProfileEntry, write_native_event};
+use std::{env, fs, io, mem, panic, thread};
+use std::panic::AssertUnwindSafe;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use crate::native_resp::{NativeResponse, NativeResponseEvent, NativeResponseProfileListProfileEntry, write_native_event};
 use crate::profiles::{read_profiles, ProfilesIniState};
 use crate::options::{read_global_options, native_notify_updated_options};
-use crate::storage::{global_options_data_path};
+use crate::storage::{ipc_secret_path, portable_dir};
+use crate::config::{native_notify_updated_connector_config, reload_configuration};
 use cfg_if::cfg_if;
-use eyre::ContextCompat;
-use nng::{Message, Protocol, Socket};
+use eyre::{eyre, ContextCompat};
+use nng::{Listener, Message, Protocol, Socket};
+use once_cell::sync::Lazy;
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
 use nng::options::{Options, RecvTimeout, SendTimeout};
 use serde::{Serialize, Deserialize};
 use crate::AppContext;
+use crate::state::AppState;
 use crate::avatars::{update_and_native_notify_avatars};
 use crate::process::fork_browser_proc;
-use crate::profiles_order::{native_notify_updated_profile_order, OrderData};
+use crate::profiles_order::native_notify_updated_profile_order;
+use crate::routing::{native_notify_updated_routing_rules, RoutingData};
+use crate::workspaces::native_notify_updated_workspaces;
+use crate::instances::{list_live_instances, register_instance, InstanceRecord, INSTANCE_HEARTBEAT_INTERVAL};
+use crate::event_bus::{connect_event_bus_peers, event_bus_socket_name, handle_event, publish_event, setup_event_bus, ConnectorEvent};
+use crate::cmd::execute_cmd_for_message;
+use crate::native_req::{NativeMessage, NativeMessageOpenUrl};
+
+// Leaves room for the terminating null byte within sockaddr_un.sun_path
+#[cfg(target_family = "unix")]
+const MAX_UNIX_SOCKET_PATH_LEN: usize = 100;
+
+const IPC_SECRET_LEN: usize = 32;
+// Every message starts with the HMAC-SHA256 of the serialized request or response
+const IPC_TAG_LEN: usize = 32;
+
+// How long to wait for a slow command to complete after it was accepted
+const IPC_COMPLETION_TIMEOUT: Duration = Duration::from_secs(10);
+const IPC_COMPLETION_POLL_INTERVAL: Duration = Duration::from_millis(50);
+// Results of slow commands are dropped if nobody asks for them in time
+const IPC_COMPLETION_RETENTION: Duration = Duration::from_secs(60);
 
 // === IPC ===
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(tag = "t", content = "c")]
 enum IPCCommand {
     FocusWindow(FocusWindowCommand),
@@ -25,62 +54,469 @@ enum IPCCommand {
     UpdateOptions,
     UpdateAvatars,
     UpdateProfileOrder,
+    OpenUrl(OpenUrlCommand),
+    UpdateRoutingRules,
+    UpdateWorkspaces,
+    UpdateConnectorConfig,
 }
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 struct FocusWindowCommand {
-    url: Option<String>
+    url: Option<String>,
+    #[serde(default)]
+    new_window: bool
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct OpenUrlCommand {
+    url: String
+}
+#[derive(Serialize, Deserialize, Debug)]
+struct OpenUrlResult {
+    // Whether a routing rule picked the profile the URL was opened in
+    routed: bool
+}
+
+impl IPCCommand {
+    // Slow commands are accepted right away and run in the background, the sender then polls for
+    // their completion
+    fn is_slow(&self) -> bool {
+        matches!(self,
+            IPCCommand::FocusWindow(_)
+            | IPCCommand::OpenUrl(_)
+            | IPCCommand::UpdateAvatars
+            | IPCCommand::UpdateConnectorConfig)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct IpcRequest {
+    // Correlation ID chosen by the sender, echoed in the response
+    id: u64,
+    body: IpcRequestBody
+}
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "t", content = "c")]
+enum IpcRequestBody {
+    Command(IPCCommand),
+    // Ask whether the slow command with the same correlation ID has completed
+    CheckCompletion
+}
+#[derive(Serialize, Deserialize, Debug)]
+struct IpcResponse {
+    // Missing if the request could not be read
+    id: Option<u64>,
+    status: IpcResponseStatus
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "t", content = "c")]
+enum IpcResponseStatus {
+    // The command is still running
+    Accepted,
+    Completed {
+        payload: Option<serde_cbor::Value>
+    },
+    Failed {
+        kind: IpcErrorKind,
+        message: String
+    }
+}
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum IpcErrorKind {
+    // The request was not signed with our secret
+    Unauthenticated,
+    // The request could not be read
+    BadRequest,
+    // No slow command with the correlation ID was accepted, or its result was dropped
+    UnknownRequest,
+    CommandFailed
+}
+
+impl IpcResponseStatus {
+    fn from_result(result: eyre::Result<Option<serde_cbor::Value>>) -> IpcResponseStatus {
+        match result {
+            Ok(payload) => IpcResponseStatus::Completed { payload },
+            Err(e) => IpcResponseStatus::Failed {
+                kind: IpcErrorKind::CommandFailed,
+                message: format!("{:#}", e)
+            }
+        }
+    }
+
+    fn failed<S: Into<String>>(kind: IpcErrorKind, message: S) -> IpcResponseStatus {
+        IpcResponseStatus::Failed {
+            kind,
+            message: message.into()
+        }
+    }
 }
 fn get_ipc_socket_name(profile_id: &str, reset: bool) -> io::Result<String> {
     cfg_if! {
         if #[cfg(target_family = "unix")] {
-            // TODO Somehow delete unix socket afterwards? IDK, could break everything if new instance starts before we delete socket
-            let url = format!("ipc:///tmp/fps-profile_{}", profile_id);
+            let socket_path = get_ipc_socket_path(&format!("fps-profile_{}", profile_id))?;
+            if reset {
+                remove_stale_socket(&socket_path)?;
+            }
+            let url = format!("ipc://{}", socket_path.display());
             log::trace!("IPC socket for profile {:?} resolved to: {:?}", profile_id, url);
-            return Ok(url);
+            Ok(url)
         } else if #[cfg(target_family = "windows")] {
-            let name = format!("ipc://fps-profile_{}", profile_id);
+            let _ = reset;
+            // Named pipes don't live in the filesystem, portable instances only talk to the instances
+            // started from the same directory
+            let name = match portable_dir() {
+                Some(portable_dir) => format!("ipc://fps-portable-{}_{}", portable_dir_key(portable_dir), profile_id),
+                None => format!("ipc://fps-profile_{}", profile_id)
+            };
             log::trace!("IPC pipe for profile {:?} resolved to: {:?}", profile_id, name);
-            return Ok(name);
+            Ok(name)
         } else {
             compile_error!("Unknown OS!");
         }
     }
 }
 
+/// Socket of the event bus of this instance.
+pub fn get_event_bus_socket_name() -> io::Result<String> {
+    let socket_name = format!("fps-events_{}", std::process::id());
+    cfg_if! {
+        if #[cfg(target_family = "unix")] {
+            let socket_path = get_ipc_socket_path(&socket_name)?;
+            // Left behind by an earlier process with the same PID
+            remove_stale_socket(&socket_path)?;
+            Ok(format!("ipc://{}", socket_path.display()))
+        } else {
+            // PIDs are unique, so portable instances don't need their own names
+            Ok(format!("ipc://{}", socket_name))
+        }
+    }
+}
+
+#[cfg(target_family = "unix")]
+fn get_ipc_socket_path(socket_name: &str) -> io::Result<PathBuf> {
+    if let Some(portable_dir) = portable_dir() {
+        // Portable instances only talk to the instances started from the same directory
+        let socket_dir = portable_dir.join("ipc");
+        let socket_path = socket_dir.join(&socket_name);
+        // Unix socket paths are limited to around 100 bytes, so deep directories won't fit
+        if socket_path.as_os_str().len() < MAX_UNIX_SOCKET_PATH_LEN {
+            ensure_private_dir(&socket_dir)?;
+            return Ok(socket_path);
+        }
+        let socket_dir = get_user_ipc_dir()?.join(format!("portable-{}", portable_dir_key(portable_dir)));
+        ensure_private_dir(&socket_dir)?;
+        return Ok(socket_dir.join(socket_name));
+    }
+    Ok(get_user_ipc_dir()?.join(socket_name))
+}
+
+// Sockets are kept in a directory only the current user can access, so other users can neither
+// connect to them nor put their own sockets in their place
+#[cfg(target_family = "unix")]
+fn get_user_ipc_dir() -> io::Result<PathBuf> {
+    if let Some(runtime_dir) = env::var_os("XDG_RUNTIME_DIR").map(PathBuf::from).filter(|p| p.is_absolute()) {
+        let socket_dir = runtime_dir.join("ff-pswitch");
+        match ensure_private_dir(&socket_dir) {
+            Ok(()) => return Ok(socket_dir),
+            Err(e) => log::warn!("Unable to use runtime directory {:?} for IPC sockets: {:?}", socket_dir, e)
+        }
+    }
+    // Not every system has a runtime directory (e.g. macOS)
+    let socket_dir = env::temp_dir().join(format!("ff-pswitch-{}", nix::unistd::getuid()));
+    ensure_private_dir(&socket_dir)?;
+    Ok(socket_dir)
+}
+
+/// Directory holding the records of the running instances, next to their sockets.
+pub fn get_instance_registry_dir(data_dir: &Path) -> io::Result<PathBuf> {
+    cfg_if! {
+        if #[cfg(target_family = "unix")] {
+            let _ = data_dir;
+            let ipc_dir = match portable_dir() {
+                Some(portable_dir) => portable_dir.join("ipc"),
+                None => get_user_ipc_dir()?
+            };
+            ensure_private_dir(&ipc_dir)?;
+            let registry_dir = ipc_dir.join("instances");
+            ensure_private_dir(&registry_dir)?;
+            Ok(registry_dir)
+        } else {
+            // Named pipes have no directory, the data dir is private to the user as well
+            let registry_dir = data_dir.join("instances");
+            fs::create_dir_all(&registry_dir)?;
+            Ok(registry_dir)
+        }
+    }
+}
+
+#[cfg(target_family = "unix")]
+fn ensure_private_dir(dir: &Path) -> io::Result<()> {
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+
+    match fs::DirBuilder::new().mode(0o700).create(dir) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+        Err(e) => return Err(e)
+    }
+
+    // In shared directories like /tmp someone else may have created it before us
+    let metadata = fs::symlink_metadata(dir)?;
+    if !metadata.is_dir() {
+        return Err(io::Error::new(io::ErrorKind::Other, format!("{:?} is not a directory", dir)));
+    }
+    if metadata.uid() != nix::unistd::getuid().as_raw() {
+        return Err(io::Error::new(io::ErrorKind::PermissionDenied, format!("{:?} is owned by another user", dir)));
+    }
+    if metadata.mode() & 0o077 != 0 {
+        log::warn!("IPC directory {:?} is accessible by other users, restricting it", dir);
+        fs::set_permissions(dir, fs::Permissions::from_mode(0o700))?;
+    }
+    Ok(())
+}
+
+// A connector that crashed or was killed leaves its socket behind, which would stop us from listening
+#[cfg(target_family = "unix")]
+fn remove_stale_socket(socket_path: &Path) -> io::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+
+    let metadata = match fs::symlink_metadata(socket_path) {
+        Ok(m) => m,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e)
+    };
+    if !metadata.file_type().is_socket() {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{:?} exists and is not a socket", socket_path)));
+    }
+    if dial_socket(&format!("ipc://{}", socket_path.display())) {
+        return Err(io::Error::new(io::ErrorKind::AddrInUse, format!("another connector is already listening on {:?}", socket_path)));
+    }
+    log::info!("Removing stale IPC socket: {:?}", socket_path);
+    fs::remove_file(socket_path)
+}
+
+fn portable_dir_key(portable_dir: &Path) -> String {
+    let digest = ring::digest::digest(&ring::digest::SHA256, portable_dir.to_string_lossy().as_bytes());
+    data_encoding::HEXLOWER.encode(&digest.as_ref()[..8])
+}
+
+// Only processes that can read the secret in the config dir, i.e. connectors of the same user, are
+// allowed to send us commands
+pub fn load_ipc_key(config_dir: &Path) -> io::Result<hmac::Key> {
+    let secret_path = ipc_secret_path(config_dir);
+    let secret = match fs::read(&secret_path) {
+        Ok(s) => {
+            restrict_ipc_secret_permissions(&secret_path)?;
+            s
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => create_ipc_secret(&secret_path)?,
+        Err(e) => return Err(e)
+    };
+    if secret.len() < IPC_SECRET_LEN {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("IPC secret {:?} is too short", secret_path)));
+    }
+    Ok(hmac::Key::new(hmac::HMAC_SHA256, &secret))
+}
+
+fn create_ipc_secret(secret_path: &Path) -> io::Result<Vec<u8>> {
+    let mut secret = vec![0u8; IPC_SECRET_LEN];
+    SystemRandom::new().fill(&mut secret)
+        .map_err(|_| io::Error::new(io::ErrorKind::Other, "failed to generate IPC secret"))?;
+
+    // Write the secret elsewhere and link it into place, so other instances starting at the same time
+    // never read a partially written secret and all of them end up using the same one
+    let mut tmp_path = secret_path.as_os_str().to_owned();
+    tmp_path.push(format!(".{}.tmp", std::process::id()));
+    let tmp_path = PathBuf::from(tmp_path);
+    let mut options = fs::OpenOptions::new();
+    options.create(true).truncate(true).write(true);
+    #[cfg(target_family = "unix")]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let result = options.open(&tmp_path)
+        .and_then(|mut file| {
+            file.write_all(&secret)?;
+            file.sync_all()
+        })
+        .and_then(|_| fs::hard_link(&tmp_path, secret_path));
+    let _ = fs::remove_file(&tmp_path);
+    match result {
+        Ok(()) => {
+            log::info!("Generated IPC secret: {:?}", secret_path);
+            Ok(secret)
+        }
+        // Another instance won the race
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => fs::read(secret_path),
+        Err(e) => Err(e)
+    }
+}
+
+fn restrict_ipc_secret_permissions(secret_path: &Path) -> io::Result<()> {
+    cfg_if! {
+        if #[cfg(target_family = "unix")] {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(secret_path)?.permissions().mode();
+            if mode & 0o077 != 0 {
+                log::warn!("IPC secret {:?} is accessible by other users, restricting it", secret_path);
+                fs::set_permissions(secret_path, fs::Permissions::from_mode(0o600))?;
+            }
+            Ok(())
+        } else {
+            // The config dir is private to the user
+            let _ = secret_path;
+            Ok(())
+        }
+    }
+}
+
+pub fn sign_ipc_message(key: &hmac::Key, payload: &[u8]) -> Vec<u8> {
+    let tag = hmac::sign(key, payload);
+    let mut message = Vec::with_capacity(IPC_TAG_LEN + payload.len());
+    message.extend_from_slice(tag.as_ref());
+    message.extend_from_slice(payload);
+    message
+}
+
+// Returns the payload if the message was signed with our key
+pub fn verify_ipc_message<'a>(key: &hmac::Key, message: &'a [u8]) -> Option<&'a [u8]> {
+    if message.len() < IPC_TAG_LEN {
+        return None;
+    }
+    let (tag, payload) = message.split_at(IPC_TAG_LEN);
+    hmac::verify(key, payload, tag).ok().map(|_| payload)
+}
+
 fn handle_conn(context: &AppContext, server: &Socket, msg: Message) {
-    // Read command
-    let mut deserializer = serde_cbor::Deserializer::from_slice(msg.as_slice());
-    match IPCCommand::deserialize(&mut deserializer) {
-        Ok(command) => {
+    let key = match load_ipc_key(&context.state().config_dir) {
+        Ok(k) => k,
+        Err(e) => {
+            log::error!("Failed to load IPC secret, unable to answer IPC request: {:?}", e);
+            return
+        }
+    };
+
+    let response = match read_ipc_request(&key, msg.as_slice()) {
+        Ok(request) => IpcResponse {
+            id: Some(request.id),
+            status: handle_ipc_request(context, request)
+        },
+        Err(status) => IpcResponse {
+            id: None,
+            status
+        }
+    };
+
+    // Write response
+    let serialized = match serde_cbor::to_vec(&response) {
+        Ok(s) => s,
+        Err(e) => {
+            log::error!("Failed to serialize IPC response: {:?}", e);
+            return
+        }
+    };
+    if let Err(e) = server.send(Message::from(sign_ipc_message(&key, &serialized).as_slice())) {
+        log::error!("IPC error while writing response: {:?}", e);
+        return
+    }
+}
+
+fn read_ipc_request(key: &hmac::Key, message: &[u8]) -> Result<IpcRequest, IpcResponseStatus> {
+    let payload = match verify_ipc_message(key, message) {
+        Some(p) => p,
+        None => {
+            log::warn!("Rejected unauthenticated IPC request ({} bytes)", message.len());
+            return Err(IpcResponseStatus::failed(IpcErrorKind::Unauthenticated, "request is not signed with the IPC secret"))
+        }
+    };
+
+    // Read request
+    let mut deserializer = serde_cbor::Deserializer::from_slice(payload);
+    IpcRequest::deserialize(&mut deserializer).map_err(|e| {
+        log::error!("Failed to read request from IPC: {:?}", e);
+        IpcResponseStatus::failed(IpcErrorKind::BadRequest, e.to_string())
+    })
+}
+
+// Results of the slow commands, by correlation ID, with the time they were accepted or completed.
+// Commands that are still running have no result yet.
+static SLOW_COMMANDS: Lazy<Mutex<HashMap<u64, (Instant, Option<IpcResponseStatus>)>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+fn handle_ipc_request(context: &AppContext, request: IpcRequest) -> IpcResponseStatus {
+    let id = request.id;
+    match request.body {
+        IpcRequestBody::Command(command) if command.is_slow() => {
+            {
+                let mut slow_commands = SLOW_COMMANDS.lock().unwrap();
+                // Commands that never completed are dropped as well, nobody waits for them that long
+                slow_commands.retain(|_, (since, _)| since.elapsed() < IPC_COMPLETION_RETENTION);
+                slow_commands.insert(id, (Instant::now(), None));
+            }
             let context_clone = context.clone();
             // Windows doesn't seem to like it if we block when reading from a named pipe
             //   So instead handle the command in a new thread to avoid doing expensive stuff
             //   in the IPC thread.
-            thread::spawn(move || handle_ipc_cmd(&context_clone, command));
+            thread::spawn(move || {
+                // The sender would otherwise wait for a result that never comes
+                let status = match panic::catch_unwind(AssertUnwindSafe(|| handle_ipc_cmd(&context_clone, command))) {
+                    Ok(result) => IpcResponseStatus::from_result(result),
+                    Err(_) => IpcResponseStatus::failed(IpcErrorKind::CommandFailed, "the command panicked")
+                };
+                SLOW_COMMANDS.lock().unwrap().insert(id, (Instant::now(), Some(status)));
+            });
+            IpcResponseStatus::Accepted
         }
-        Err(e) => {
-            log::error!("Failed to read command from IPC: {:?}", e);
-            return
+        IpcRequestBody::Command(command) => IpcResponseStatus::from_result(handle_ipc_cmd(context, command)),
+        IpcRequestBody::CheckCompletion => {
+            let mut slow_commands = SLOW_COMMANDS.lock().unwrap();
+            match slow_commands.get(&id) {
+                None => IpcResponseStatus::failed(IpcErrorKind::UnknownRequest, format!("no command with ID {} was accepted", id)),
+                Some((_, None)) => IpcResponseStatus::Accepted,
+                Some((_, Some(_))) => slow_commands.remove(&id).and_then(|(_, status)| status).unwrap()
+            }
         }
     }
+}
 
-    // TODO Write different status if command failed
-    // Write command status
-    if let Err(e) = server.send(Message::from([0])) {
-        log::error!("IPC error while writing command status: {:?}", e);
-        return
-    }
+// The IPC server is bound to the socket of the current profile, which can change when the
+// connector is reinitialized
+struct IpcServer {
+    socket: Socket,
+    listener: Listener,
+    profile_id: String,
+    socket_name: String
 }
 
+static IPC_SERVER: Lazy<Mutex<Option<IpcServer>>> = Lazy::new(|| Mutex::new(None));
+
 pub fn setup_ipc(context: &AppContext) -> eyre::Result<()> {
     log::trace!("Starting IPC server...");
-    let socket_name = get_ipc_socket_name(context.state
-                                              .cur_profile_id
-                                              .as_ref()
-                                              .context("Missing profile ID!")?, true)?;
-
     let server = Socket::new(Protocol::Rep0)?;
-    server.listen(&socket_name)?;
+    {
+        let mut ipc_server = IPC_SERVER.lock().unwrap();
+        let profile_id = context.state()
+            .cur_profile_id
+            .clone()
+            .context("Missing profile ID!")?;
+        let socket_name = get_ipc_socket_name(&profile_id, true)?;
+        let listener = Listener::new(&server, &socket_name)?;
+        *ipc_server = Some(IpcServer {
+            socket: server.clone(),
+            listener,
+            profile_id,
+            socket_name
+        });
+    }
+
+    if let Err(e) = setup_event_bus(context) {
+        log::error!("Failed to join event bus: {:?}", e);
+    }
+
+    // Let other instances know we are listening
+    let context_clone = context.clone();
+    thread::spawn(move || loop {
+        refresh_instance_record(&context_clone);
+        thread::sleep(INSTANCE_HEARTBEAT_INTERVAL);
+    });
+
     loop {
         let msg = server.recv()?;
 
@@ -88,50 +524,130 @@ pub fn setup_ipc(context: &AppContext) -> eyre::Result<()> {
     }
 }
 
-fn handle_ipc_cmd(context: &AppContext, cmd: IPCCommand) {
+/// Move the IPC server to the socket of the current profile if it changed.
+pub fn rebind_ipc(context: &AppContext) -> eyre::Result<()> {
+    let profile_id = context.state()
+        .cur_profile_id
+        .clone()
+        .context("Missing profile ID!")?;
+    let mut ipc_server = IPC_SERVER.lock().unwrap();
+    let server = match ipc_server.as_mut() {
+        Some(s) => s,
+        // The server binds to the current profile once it starts
+        None => return Ok(())
+    };
+    if server.profile_id == profile_id {
+        return Ok(());
+    }
+
+    log::trace!("Rebinding IPC server from profile {} to {}", server.profile_id, profile_id);
+    let socket_name = get_ipc_socket_name(&profile_id, true)?;
+    let listener = Listener::new(&server.socket, &socket_name)?;
+    mem::replace(&mut server.listener, listener).close();
+    server.profile_id = profile_id;
+    server.socket_name = socket_name;
+    drop(ipc_server);
+
+    refresh_instance_record(context);
+    Ok(())
+}
+
+fn refresh_instance_record(context: &AppContext) {
+    let (profile_id, socket_name) = match IPC_SERVER.lock().unwrap().as_ref() {
+        Some(server) => (server.profile_id.clone(), server.socket_name.clone()),
+        None => return
+    };
+    let registry_dir = match get_instance_registry_dir(&context.state().data_dir) {
+        Ok(d) => d,
+        Err(e) => {
+            log::error!("Failed to open instance registry: {:?}", e);
+            return
+        }
+    };
+    if let Err(e) = register_instance(&registry_dir, &profile_id, &socket_name, event_bus_socket_name().as_deref()) {
+        log::error!("Failed to register instance: {:?}", e);
+    }
+    match list_live_instances(&registry_dir) {
+        Ok(instances) => connect_event_bus_peers(&instances),
+        Err(e) => log::error!("Failed to list running instances: {:?}", e)
+    }
+}
+
+fn handle_ipc_cmd(context: &AppContext, cmd: IPCCommand) -> eyre::Result<Option<serde_cbor::Value>> {
     log::trace!("Executing IPC command: {:?}", cmd);
 
-    match cmd {
-        IPCCommand::FocusWindow(options) => handle_ipc_cmd_focus_window(context.state, options),
+    let payload = match cmd {
+        IPCCommand::FocusWindow(options) => {
+            handle_ipc_cmd_focus_window(context, options)?;
+            None
+        }
         IPCCommand::UpdateProfileList => {
-            match read_profiles(&context.state.config, &context.state.config_dir) {
-                Ok(profiles) => {
-                    if let Some(pid) = &context.state.cur_profile_id
-                        .as_ref()
-                        .map(|it| it.clone()) {
-                        // Notify updated profile list
-                        write_native_event(NativeResponseEvent::ProfileList {
-                            current_profile_id: pid.to_owned(),
-                            profiles: profiles.profile_entries.iter().map(NativeResponseProfileListProfileEntry::from_profile_entry).collect()
-                        });
-                    }
-                },
-                Err(e) => {
-                    log::error!("Failed to update profile list: {:?}", e);
-                }
-            };
+            native_notify_profile_list(&context.state());
+            None
         }
         IPCCommand::CloseManager => {
             write_native_event(NativeResponseEvent::CloseManager);
+            None
         }
         IPCCommand::UpdateOptions => {
-            native_notify_updated_options(context.state);
+            native_notify_updated_options(&context.state());
+            None
         }
         IPCCommand::UpdateAvatars => {
             update_and_native_notify_avatars(context);
+            None
         }
         IPCCommand::UpdateProfileOrder => {
-            native_notify_updated_profile_order(context.state);
+            native_notify_updated_profile_order(&context.state());
+            None
         }
-    }
+        IPCCommand::OpenUrl(options) => {
+            let result = handle_ipc_cmd_open_url(context, options)?;
+            Some(serde_cbor::value::to_value(result)?)
+        }
+        IPCCommand::UpdateRoutingRules => {
+            native_notify_updated_routing_rules(&context.state());
+            None
+        }
+        IPCCommand::UpdateWorkspaces => {
+            native_notify_updated_workspaces(&context.state());
+            None
+        }
+        IPCCommand::UpdateConnectorConfig => {
+            reload_configuration(context);
+            native_notify_updated_connector_config(&context.state());
+            // The profiles may now come from a different profile root
+            native_notify_profile_list(&context.state());
+            None
+        }
+    };
 
     log::trace!("Execution complete!");
+    Ok(payload)
+}
+
+pub fn native_notify_profile_list(app_state: &AppState) {
+    match read_profiles(&app_state.config, &app_state.config_dir) {
+        Ok(profiles) => {
+            if let Some(pid) = &app_state.cur_profile_id {
+                // Notify updated profile list
+                write_native_event(NativeResponseEvent::ProfileList {
+                    current_profile_id: pid.to_owned(),
+                    profiles: profiles.profile_entries.iter().map(NativeResponseProfileListProfileEntry::from_profile_entry).collect()
+                });
+            }
+        },
+        Err(e) => {
+            log::error!("Failed to update profile list: {:?}", e);
+        }
+    };
 }
 
-fn handle_ipc_cmd_focus_window(app_state: &AppState, cmd: FocusWindowCommand) {
+fn handle_ipc_cmd_focus_window(context: &AppContext, cmd: FocusWindowCommand) -> eyre::Result<()> {
+    let app_state = context.state();
     if let Some(extension_id) = app_state.internal_extension_id.as_ref() {
         if let Some(cur_profile_id) = app_state.cur_profile_id.as_ref() {
-            let global_options = read_global_options(&global_options_data_path(&app_state.config_dir));
+            let global_options = read_global_options(&app_state.config_dir);
             if global_options["windowFocusWorkaround"] == serde_json::Value::Bool(true) {
                 if let Ok(profiles) = read_profiles(&app_state.config, &app_state.config_dir) {
                     if let Some(cur_profile) = profiles.profile_entries
@@ -141,8 +657,15 @@ fn handle_ipc_cmd_focus_window(app_state: &AppState, cmd: FocusWindowCommand) {
                             Some(url) => url,
                             None => format!("moz-extension://{}/src/entries/winfocus/index.html", extension_id)
                         };
-                        fork_browser_proc(app_state, cur_profile, Some(url));
-                        return;
+                        match fork_browser_proc(&app_state, cur_profile, Some(url), cmd.new_window) {
+                            Ok(Some(spawned)) => { context.processes.register(&cur_profile.id, spawned); },
+                            Ok(None) => {}
+                            Err(e) => {
+                                log::error!("Failed to launch browser to focus window: {:?}", e);
+                                return Err(eyre!("failed to launch browser to focus window: {:?}", e));
+                            }
+                        }
+                        return Ok(());
                     }
                 }
             }
@@ -150,90 +673,381 @@ fn handle_ipc_cmd_focus_window(app_state: &AppState, cmd: FocusWindowCommand) {
     }
     // Focus window
     write_native_event(NativeResponseEvent::FocusWindow {
-        url: cmd.url
+        url: cmd.url,
+        new_window: cmd.new_window
     });
+    Ok(())
+}
+
+// Check whether the connector of the specified profile is listening
+pub fn probe_instance(profile_id: &str) -> bool {
+    match get_ipc_socket_name(profile_id, false) {
+        Ok(socket_name) => dial_socket(&socket_name),
+        Err(_) => false
+    }
+}
+
+fn dial_socket(socket_name: &str) -> bool {
+    match Socket::new(Protocol::Req0) {
+        Ok(conn) => conn.dial(socket_name).is_ok(),
+        Err(_) => false
+    }
+}
+
+fn handle_ipc_cmd_open_url(context: &AppContext, cmd: OpenUrlCommand) -> eyre::Result<OpenUrlResult> {
+    let routed = read_profiles(&context.state().config, &context.state().config_dir)
+        .map(|profiles| RoutingData::read(&context.state().config_dir)
+            .route(&profiles, &cmd.url)
+            .is_some())
+        .unwrap_or(false);
+
+    if routed {
+        let resp = execute_cmd_for_message(context, NativeMessage::OpenUrl(NativeMessageOpenUrl {
+            url: cmd.url
+        }));
+        log::trace!("Routed URL opened over IPC: {:?}", resp);
+        if let NativeResponse::Error { error, .. } = resp {
+            return Err(eyre!("failed to open routed URL: {}", error));
+        }
+    } else {
+        // No rule wants the URL, so open it right here
+        handle_ipc_cmd_focus_window(context, FocusWindowCommand {
+            url: Some(cmd.url),
+            new_window: false
+        })?;
+    }
+    Ok(OpenUrlResult { routed })
 }
 
 #[derive(Debug)]
 pub enum IpcError {
-    BadStatus,
     SerializationError(serde_cbor::Error),
     IoError(io::Error),
-    NetworkError(nng::Error)
+    NetworkError(nng::Error),
+    // The response was not signed with our secret
+    Unauthenticated,
+    // The response does not belong to the request
+    BadResponse,
+    CommandFailed {
+        kind: IpcErrorKind,
+        message: String
+    },
+    TimedOut
+}
+
+fn next_ipc_request_id() -> Result<u64, IpcError> {
+    // Random, so requests of different senders never share an ID
+    let mut id = [0u8; 8];
+    SystemRandom::new().fill(&mut id)
+        .map_err(|_| IpcError::IoError(io::Error::new(io::ErrorKind::Other, "failed to generate IPC request ID")))?;
+    Ok(u64::from_le_bytes(id))
 }
 
-fn send_ipc_cmd(context: &AppContext, target_profile_id: &str, cmd: IPCCommand) -> std::result::Result<(), IpcError> {
+fn send_ipc_cmd(context: &AppContext, target_profile_id: &str, cmd: IPCCommand) -> std::result::Result<Option<serde_cbor::Value>, IpcError> {
     log::trace!("Sending IPC command {:?} to profile: {}", cmd, target_profile_id);
-    let cur_profile_id = context.state.cur_profile_id.as_deref();
+    let app_state = context.state();
+    let cur_profile_id = app_state.cur_profile_id.as_deref();
     if cur_profile_id.is_some() && cur_profile_id.unwrap() == target_profile_id {
         log::trace!("Fast-pathing IPC command...");
-        handle_ipc_cmd(context, cmd);
-        Ok(())
-    } else {
-        let socket_name = get_ipc_socket_name(target_profile_id, false)
-            .map_err(IpcError::IoError)?;
-
-        let conn = Socket::new(Protocol::Req0).map_err(IpcError::NetworkError)?;
-        conn.set_opt::<SendTimeout>(Some(Duration::from_millis(500)));
-        conn.set_opt::<RecvTimeout>(Some(Duration::from_millis(3000)));
-        conn.dial(&socket_name).map_err(IpcError::NetworkError)?;
-        log::trace!("Writing IPC command...");
-        let serialized = serde_cbor::to_vec(&cmd)
-            .map_err(IpcError::SerializationError)?;
-        conn.send(Message::from(&serialized));
-        log::trace!("IPC command written, reading status...");
-        let resp = conn.recv()
-            .map_err(IpcError::NetworkError)?;
-        let status = resp.first().unwrap_or(&1);
-        log::trace!("IPC command status is: {}", status);
-        if *status == 0 {
-            Ok(())
-        } else {
-            Err(IpcError::BadStatus)
+        return handle_ipc_cmd(context, cmd).map_err(|e| IpcError::CommandFailed {
+            kind: IpcErrorKind::CommandFailed,
+            message: format!("{:#}", e)
+        });
+    }
+
+    let socket_name = get_ipc_socket_name(target_profile_id, false)
+        .map_err(IpcError::IoError)?;
+    send_ipc_cmd_to_socket(context, &socket_name, cmd)
+}
+
+// Send the command to the instance listening on the socket, bypassing the fast path
+fn send_ipc_cmd_to_socket(context: &AppContext, socket_name: &str, cmd: IPCCommand) -> std::result::Result<Option<serde_cbor::Value>, IpcError> {
+    let key = load_ipc_key(&context.state().config_dir)
+        .map_err(IpcError::IoError)?;
+
+    let conn = Socket::new(Protocol::Req0).map_err(IpcError::NetworkError)?;
+    conn.set_opt::<SendTimeout>(Some(Duration::from_millis(500)));
+    conn.set_opt::<RecvTimeout>(Some(Duration::from_millis(3000)));
+    conn.dial(socket_name).map_err(IpcError::NetworkError)?;
+
+    let id = next_ipc_request_id()?;
+    log::trace!("Writing IPC command with ID {}...", id);
+    let mut status = send_ipc_request(&conn, &key, IpcRequest { id, body: IpcRequestBody::Command(cmd) })?;
+    let deadline = Instant::now() + IPC_COMPLETION_TIMEOUT;
+    loop {
+        log::trace!("IPC command status is: {:?}", status);
+        match status {
+            IpcResponseStatus::Completed { payload } => return Ok(payload),
+            IpcResponseStatus::Failed { kind, message } => return Err(IpcError::CommandFailed { kind, message }),
+            IpcResponseStatus::Accepted => {}
         }
+        if Instant::now() >= deadline {
+            return Err(IpcError::TimedOut);
+        }
+        thread::sleep(IPC_COMPLETION_POLL_INTERVAL);
+        status = send_ipc_request(&conn, &key, IpcRequest { id, body: IpcRequestBody::CheckCompletion })?;
+    }
+}
+
+fn send_ipc_request(conn: &Socket, key: &hmac::Key, request: IpcRequest) -> Result<IpcResponseStatus, IpcError> {
+    let serialized = serde_cbor::to_vec(&request)
+        .map_err(IpcError::SerializationError)?;
+    conn.send(Message::from(sign_ipc_message(key, &serialized).as_slice()))
+        .map_err(|(_, e)| IpcError::NetworkError(e))?;
+    let resp = conn.recv()
+        .map_err(IpcError::NetworkError)?;
+    let payload = verify_ipc_message(key, resp.as_slice())
+        .ok_or(IpcError::Unauthenticated)?;
+    let response: IpcResponse = serde_cbor::from_slice(payload)
+        .map_err(IpcError::SerializationError)?;
+    match response.id {
+        Some(id) if id == request.id => Ok(response.status),
+        // The request could not be read at all
+        None => match response.status {
+            status @ IpcResponseStatus::Failed { .. } => Ok(status),
+            _ => Err(IpcError::BadResponse)
+        },
+        Some(_) => Err(IpcError::BadResponse)
     }
 }
 
 // Notify another instance to focus it's window
-pub fn notify_focus_window(context: &AppContext, target_profile_id: &String, url: Option<String>) -> Result<(), IpcError> {
+pub fn notify_focus_window(context: &AppContext, target_profile_id: &String, url: Option<String>, new_window: bool) -> Result<(), IpcError> {
     send_ipc_cmd(context, target_profile_id, IPCCommand::FocusWindow(FocusWindowCommand {
+        url,
+        new_window
+    })).map(|_| ())
+}
+
+// Ask another instance to open a URL, respecting the routing rules
+pub fn notify_open_url(context: &AppContext, target_profile_id: &String, url: String) -> Result<(), IpcError> {
+    send_ipc_cmd(context, target_profile_id, IPCCommand::OpenUrl(OpenUrlCommand {
         url
-    }))
+    })).map(|_| ())
+}
+
+// Send the command to the running instances of the profiles in parallel
+fn broadcast_ipc_cmd(context: &AppContext, profiles: &ProfilesIniState, cmd: IPCCommand, include_current: bool) {
+    let cur_profile_id = context.state().cur_profile_id.clone();
+    let mut targets: Vec<IpcTarget> = match get_instance_registry_dir(&context.state().data_dir).and_then(|d| list_live_instances(&d)) {
+        Ok(instances) => instances.into_iter()
+            .filter(|instance| profiles.profile_entries.iter().any(|p| p.id == instance.profile_id))
+            .map(IpcTarget::from_instance)
+            .collect(),
+        Err(e) => {
+            log::warn!("Failed to list running instances, notifying every profile: {:?}", e);
+            profiles.profile_entries.iter().map(|p| IpcTarget::from_profile_id(&p.id)).collect()
+        }
+    };
+    targets.retain(|target| Some(&target.profile_id) != cur_profile_id.as_ref());
+    if include_current {
+        // We handle the command ourselves even if our record is missing
+        if let Some(cur_profile_id) = cur_profile_id {
+            if profiles.profile_entries.iter().any(|p| p.id == cur_profile_id) {
+                targets.push(IpcTarget::from_profile_id(&cur_profile_id));
+            }
+        }
+    }
+    send_ipc_cmd_in_parallel(context, targets, cmd);
+}
+
+// An instance to send a command to
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+struct IpcTarget {
+    profile_id: String,
+    // Socket the instance listens on, derived from the profile ID if we don't know it
+    socket: Option<String>
+}
+
+impl IpcTarget {
+    fn from_instance(instance: InstanceRecord) -> IpcTarget {
+        IpcTarget { profile_id: instance.profile_id, socket: Some(instance.socket) }
+    }
+
+    fn from_profile_id(profile_id: &str) -> IpcTarget {
+        IpcTarget { profile_id: profile_id.to_owned(), socket: None }
+    }
+}
+
+fn send_ipc_cmd_in_parallel(context: &AppContext, mut targets: Vec<IpcTarget>, cmd: IPCCommand) {
+    targets.sort();
+    targets.dedup();
+
+    let senders: Vec<_> = targets.into_iter()
+        .map(|target| {
+            let context = context.clone();
+            let cmd = cmd.clone();
+            thread::spawn(move || {
+                let result = match &target.socket {
+                    Some(socket_name) => send_ipc_cmd_to_socket(&context, socket_name, cmd),
+                    None => send_ipc_cmd(&context, &target.profile_id, cmd)
+                };
+                if let Err(e) = result {
+                    log::warn!("Failed to notify profile {}: {:?}", target.profile_id, e);
+                }
+            })
+        })
+        .collect();
+    for sender in senders {
+        let _ = sender.join();
+    }
+}
+
+// Publish the event to every instance on the event bus. Without the bus, e.g. when running from
+// the command line, the matching command is sent to every instance instead.
+fn publish_or_broadcast(context: &AppContext, profiles: &ProfilesIniState, event: ConnectorEvent, cmd: IPCCommand) {
+    if !publish_event(context, event) {
+        broadcast_ipc_cmd(context, profiles, cmd, true);
+        return;
+    }
+    // The bus doesn't deliver our own events back to us
+    handle_event(context, event);
+
+    // Instances without an event bus only understand the command
+    let targets: Vec<IpcTarget> = match get_instance_registry_dir(&context.state().data_dir).and_then(|d| list_live_instances(&d)) {
+        Ok(instances) => instances.into_iter()
+            .filter(|instance| instance.events_socket.is_none() && instance.pid != std::process::id())
+            .filter(|instance| profiles.profile_entries.iter().any(|p| p.id == instance.profile_id))
+            .map(IpcTarget::from_instance)
+            .collect(),
+        Err(e) => {
+            log::warn!("Failed to list running instances, instances without an event bus miss {:?}: {:?}", event, e);
+            Vec::new()
+        }
+    };
+    send_ipc_cmd_in_parallel(context, targets, cmd);
 }
 
 // Notify all running instances to update their profile list
 pub fn notify_profile_changed(context: &AppContext, profiles: &ProfilesIniState) {
-    for profile in &profiles.profile_entries {
-        send_ipc_cmd(context, &profile.id, IPCCommand::UpdateProfileList);
-    }
+    publish_or_broadcast(context, profiles, ConnectorEvent::ProfileListChanged, IPCCommand::UpdateProfileList);
 }
 
 // Notify all running instances to update their options
 pub fn notify_options_changed(context: &AppContext, profiles: &ProfilesIniState) {
-    for profile in &profiles.profile_entries {
-        send_ipc_cmd(context, &profile.id, IPCCommand::UpdateOptions);
-    }
+    publish_or_broadcast(context, profiles, ConnectorEvent::OptionsChanged, IPCCommand::UpdateOptions);
 }
 
 // Notify all other running instances to close their managers
 pub fn notify_close_manager(context: &AppContext, profiles: &ProfilesIniState) {
-    for profile in &profiles.profile_entries {
-        if Some(&profile.id) != context.state.cur_profile_id.as_ref() {
-            send_ipc_cmd(context, &profile.id, IPCCommand::CloseManager);
-        }
-    }
+    broadcast_ipc_cmd(context, profiles, IPCCommand::CloseManager, false);
 }
 
 // Notify all running instances to update their avatars
 pub fn notify_update_avatars(context: &AppContext, profiles: &ProfilesIniState) {
-    for profile in &profiles.profile_entries {
-        send_ipc_cmd(context, &profile.id, IPCCommand::UpdateAvatars);
-    }
+    publish_or_broadcast(context, profiles, ConnectorEvent::AvatarsChanged, IPCCommand::UpdateAvatars);
 }
 
 // Notify all running instances to update their profile order
 pub fn notify_update_profile_order(context: &AppContext, profiles: &ProfilesIniState) {
-    for profile in &profiles.profile_entries {
-        send_ipc_cmd(context, &profile.id, IPCCommand::UpdateProfileOrder);
+    publish_or_broadcast(context, profiles, ConnectorEvent::OrderChanged, IPCCommand::UpdateProfileOrder);
+}
+
+// Notify all running instances to update their routing rules
+pub fn notify_update_routing_rules(context: &AppContext, profiles: &ProfilesIniState) {
+    broadcast_ipc_cmd(context, profiles, IPCCommand::UpdateRoutingRules, true);
+}
+
+// Notify all running instances to update their workspaces
+pub fn notify_update_workspaces(context: &AppContext, profiles: &ProfilesIniState) {
+    broadcast_ipc_cmd(context, profiles, IPCCommand::UpdateWorkspaces, true);
+}
+
+// Notify all other running instances to reload the connector config
+pub fn notify_update_connector_config(context: &AppContext, profiles: &ProfilesIniState) {
+    broadcast_ipc_cmd(context, profiles, IPCCommand::UpdateConnectorConfig, false);
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use std::fs;
+    use std::io;
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+    use std::os::unix::net::UnixListener;
+    use std::path::{Path, PathBuf};
+    use nng::{Protocol, Socket};
+    use super::{ensure_private_dir, remove_stale_socket};
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("fps-test-ipc-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn mode(path: &Path) -> u32 {
+        fs::metadata(path).unwrap().mode() & 0o777
+    }
+
+    #[test]
+    fn private_dir_is_created_private() {
+        let parent = test_dir("private-new");
+        let dir = parent.join("ipc");
+        ensure_private_dir(&dir).unwrap();
+        assert_eq!(mode(&dir), 0o700);
+        fs::remove_dir_all(&parent).unwrap();
+    }
+
+    #[test]
+    fn private_dir_is_restricted_if_it_exists() {
+        let dir = test_dir("private-existing");
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o755)).unwrap();
+        ensure_private_dir(&dir).unwrap();
+        assert_eq!(mode(&dir), 0o700);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn private_dir_of_another_user_is_rejected() {
+        let parent = test_dir("private-foreign");
+        let dir = if nix::unistd::getuid().is_root() {
+            let dir = parent.join("ipc");
+            fs::create_dir(&dir).unwrap();
+            fs::set_permissions(&dir, fs::Permissions::from_mode(0o755)).unwrap();
+            nix::unistd::chown(&dir, Some(nix::unistd::Uid::from_raw(65534)), None).unwrap();
+            dir
+        } else {
+            // Owned by root
+            PathBuf::from("/")
+        };
+        let err = ensure_private_dir(&dir).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        fs::remove_dir_all(&parent).unwrap();
+    }
+
+    #[test]
+    fn stale_socket_is_removed() {
+        let dir = test_dir("socket-stale");
+        let socket_path = dir.join("ipc.sock");
+        // Closing the listener leaves the socket file behind
+        drop(UnixListener::bind(&socket_path).unwrap());
+        assert!(socket_path.exists());
+        remove_stale_socket(&socket_path).unwrap();
+        assert!(!socket_path.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn live_socket_is_kept() {
+        let dir = test_dir("socket-live");
+        let socket_path = dir.join("ipc.sock");
+        let socket = Socket::new(Protocol::Rep0).unwrap();
+        socket.listen(&format!("ipc://{}", socket_path.display())).unwrap();
+        let err = remove_stale_socket(&socket_path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+        assert!(socket_path.exists());
+        socket.close();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn non_socket_is_refused() {
+        let dir = test_dir("socket-file");
+        let socket_path = dir.join("ipc.sock");
+        fs::write(&socket_path, b"not a socket").unwrap();
+        let err = remove_stale_socket(&socket_path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(fs::read(&socket_path).unwrap(), b"not a socket");
+        fs::remove_dir_all(&dir).unwrap();
     }
 }
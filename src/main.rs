@@ -8,9 +8,21 @@ mod native_resp;
 mod ipc;
 mod cmd;
 mod process;
+mod process_registry;
 mod windowing;
 mod avatars;
 mod versions;
+mod routing;
+mod cli;
+mod workspaces;
+mod browsers;
+mod desktop_entries;
+mod profile_root;
+mod diagnostics;
+mod stores;
+mod metadata;
+mod instances;
+mod event_bus;
 
 extern crate ini;
 extern crate serde;
@@ -27,6 +39,7 @@ extern crate url;
 extern crate chrono;
 extern crate rand;
 extern crate serde_cbor;
+extern crate arc_swap;
 
 cfg_if! {
     if #[cfg(target_family = "unix")] {
@@ -39,34 +52,42 @@ cfg_if! {
 }
 
 use std::{io, env, thread};
+use std::process::exit;
 use std::collections::HashMap;
 use std::fs;
-use std::sync::{Arc, RwLock};
 use cfg_if::cfg_if;
-use directories::ProjectDirs;
-use indexmap::IndexMap;
 use rand::Rng;
 use crate::avatars::update_and_native_notify_avatars;
-use crate::config::{read_configuration};
+use crate::config::{read_configuration, set_profile_root_override};
+use crate::storage::{connector_config_path, portable_dir, StorageOverrides};
 use crate::state::{AppContext, AppState};
 use crate::native_resp::{NativeResponseEvent, write_native_response, NativeResponseWrapper, NativeResponse, write_native_event};
 use crate::cmd::{execute_cmd_for_message, execute_init_cmd};
 use crate::ipc::setup_ipc;
+use crate::process_registry::run_process_monitor;
 use crate::native_req::{read_incoming_message};
 use crate::profiles_order::native_notify_updated_profile_order;
+use crate::routing::native_notify_updated_routing_rules;
+use crate::workspaces::{autostart_workspace, native_notify_updated_workspaces};
 use crate::windowing::Windowing;
+use crate::stores::migrate_stores;
+use crate::diagnostics::{enable_native_diagnostics_events, native_notify_diagnostics};
+use crate::cli::{parse_cli_command, run_cli_command};
 
 const APP_VERSION: &'static str = env!("CARGO_PKG_VERSION");
 
-// This is the application state, it will be (mostly) immutable through the life of the application
+// This is the application state, it is replaced as a whole when the config is reloaded or the
+// connector is reinitialized
 mod state {
     use std::collections::HashMap;
     use crate::config::Config;
     use std::path::PathBuf;
     use std::sync::{Arc, RwLock};
+    use arc_swap::ArcSwap;
     use indexmap::IndexMap;
     use semver::Version;
     use ulid::Ulid;
+    use crate::process_registry::ProcessRegistry;
     use crate::windowing::WindowingHandle;
 
     #[derive(Clone, Debug)]
@@ -83,30 +104,85 @@ mod state {
 
     #[derive(Clone, Debug)]
     pub struct AppContext {
-        pub state: &'static AppState,
-        pub windowing: WindowingHandle,
-        pub avatars: Arc<RwLock<IndexMap<Ulid, PathBuf>>>
+        state: Arc<ArcSwap<AppState>>,
+        // Missing when we are running from the command line
+        pub windowing: Option<WindowingHandle>,
+        pub avatars: Arc<RwLock<IndexMap<Ulid, PathBuf>>>,
+        pub processes: ProcessRegistry
+    }
+
+    impl AppContext {
+        pub fn new(app_state: AppState, windowing: Option<WindowingHandle>) -> AppContext {
+            AppContext {
+                state: Arc::new(ArcSwap::from_pointee(app_state)),
+                windowing,
+                avatars: Arc::new(RwLock::new(IndexMap::new())),
+                processes: ProcessRegistry::new()
+            }
+        }
+
+        /// A snapshot of the app state, it is not affected by later changes.
+        pub fn state(&self) -> Arc<AppState> {
+            self.state.load_full()
+        }
+
+        /// Swap in a new app state for every thread.
+        pub fn replace_state(&self, app_state: AppState) {
+            self.state.store(Arc::new(app_state));
+        }
+
+        /// Change part of the app state without losing concurrent changes to the rest of it.
+        pub fn update_state<F: Fn(&mut AppState)>(&self, update: F) {
+            self.state.rcu(|cur| {
+                let mut app_state = AppState::clone(cur);
+                update(&mut app_state);
+                app_state
+            });
+        }
     }
 }
 
+// Send the extension the state that is not part of the initialization response
+pub fn native_notify_app_state(context: &AppContext) {
+    update_and_native_notify_avatars(context);
+    let app_state = context.state();
+    native_notify_updated_profile_order(&app_state);
+    native_notify_updated_routing_rules(&app_state);
+    native_notify_updated_workspaces(&app_state);
+    native_notify_diagnostics(&app_state.config_dir);
+}
+
 // === MAIN ===
 
 fn main() {
     // Automatically enable backtraces
     env::set_var("RUST_BACKTRACE", "full");
 
-    // Notify extension of our version
-    write_native_event(NativeResponseEvent::ConnectorInformation {
-        version: APP_VERSION.to_string()
-    });
+    let mut args: Vec<String> = env::args().collect();
+    let storage_overrides = match StorageOverrides::from_args_and_env(&mut args) {
+        Ok(o) => o,
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(2);
+        }
+    };
+    let cli_command = parse_cli_command(&args);
 
     // Calculate storage dirs
-    let project_dirs = ProjectDirs::from("ax.nd",
-                                        "nulldev",
-                                        "FirefoxProfileSwitcher")
-        .expect("Could not initialize configuration (failed to find storage dir)!");
-    let pref_dir = project_dirs.preference_dir();
-    let data_dir = project_dirs.data_local_dir();
+    let (pref_dir, data_dir) = storage_overrides.resolve_dirs();
+    let pref_dir = pref_dir.as_path();
+    let data_dir = data_dir.as_path();
+
+    // Notify extension of our version
+    if cli_command.is_none() {
+        enable_native_diagnostics_events();
+        write_native_event(NativeResponseEvent::ConnectorInformation {
+            version: APP_VERSION.to_string(),
+            config_dir: pref_dir.to_string_lossy().to_string(),
+            data_dir: data_dir.to_string_lossy().to_string(),
+            profile_root: storage_overrides.profile_root.as_ref().map(|p| p.to_string_lossy().to_string())
+        });
+    }
 
     let first_run = !data_dir.exists();
 
@@ -147,6 +223,14 @@ fn main() {
         .expect("Failed to setup logging!");
 
     log::trace!("Finished setup logging (app version: {}).", APP_VERSION);
+    log::info!("Config dir: {:?}, data dir: {:?}, profile root override: {:?}", pref_dir, data_dir, storage_overrides.profile_root);
+    if let Some(portable_dir) = portable_dir() {
+        log::info!("Running in portable mode from {:?}", portable_dir);
+    }
+
+    if let Some(profile_root) = storage_overrides.profile_root.clone() {
+        set_profile_root_override(profile_root);
+    }
 
     // Initialize Windows COM library
     cfg_if! {
@@ -159,20 +243,38 @@ fn main() {
         }
     }
 
+    // Upgrade stores written by older versions before anything reads them
+    migrate_stores(pref_dir);
+
+    // Read configuration
+    let config_path = connector_config_path(pref_dir);
+    let config = read_configuration(&config_path);
+
+    log::trace!("Configuration loaded: {:?}", &config);
+
+    // We were started from the command line (e.g. as the default browser), not by the browser
+    if let Some(cli_command) = cli_command {
+        let app_state = AppState {
+            config,
+            first_run: false,
+            cur_profile_id: None,
+            extension_id: None,
+            extension_version: None,
+            internal_extension_id: None,
+            config_dir: pref_dir.to_path_buf(),
+            data_dir: data_dir.to_path_buf(),
+        };
+        let context = AppContext::new(app_state, None);
+        exit(run_cli_command(&context, cli_command));
+    }
+
     // Find extension ID
-    let args: Vec<String> = env::args().collect();
     let extension_id = args.get(2);
     if extension_id.is_none() {
         log::warn!("Could not determine extension ID!");
     }
 
-    log::trace!("Extension id: {}", extension_id.unwrap());
-
-    // Read configuration
-    let config_path = pref_dir.join("config.json");
-    let config = read_configuration(&config_path);
-
-    log::trace!("Configuration loaded: {:?}", &config);
+    log::trace!("Extension id: {:?}", extension_id);
 
     let windowing = Windowing::new();
 
@@ -225,19 +327,11 @@ fn main() {
 
     // No longer initing, we accept any type of message now (except init messages).
 
-    log::trace!("Connector initialized, application state is now frozen, enter main loop.");
+    log::trace!("Connector initialized, enter main loop.");
 
-    // Leak the app state because we need to read it from multiple threads
-    let app_state_leaked = Box::leak(Box::new(app_state));
+    let context = AppContext::new(app_state, Some(windowing.get_handle()));
 
-    let context = AppContext {
-        state: &*app_state_leaked,
-        windowing: windowing.get_handle(),
-        avatars: Arc::new(RwLock::new(IndexMap::new()))
-    };
-
-    update_and_native_notify_avatars(&context);
-    native_notify_updated_profile_order(context.state);
+    native_notify_app_state(&context);
 
     // Begin IPC
     let context_clone = context.clone();
@@ -247,6 +341,14 @@ fn main() {
         }
     });
 
+    // Launch the workspace the user wants to start with the browser
+    let context_clone = context.clone();
+    thread::spawn(move || autostart_workspace(&context_clone));
+
+    // Keep track of running profiles
+    let context_clone = context.clone();
+    thread::spawn(move || run_process_monitor(&context_clone));
+
     thread::spawn(move || {
         let pool = threadfin::builder()
             .size(1..50)
@@ -0,0 +1,221 @@
+use std::collections::HashMap;
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::path::{Path, PathBuf};
+use eyre::Context;
+use fs2::FileExt;
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use crate::options::GlobalOptionsData;
+use crate::profiles::{AvatarData, OptionsData};
+use crate::profiles_order::OrderData;
+use crate::storage::{avatar_data_path, global_options_data_path, metadata_data_path, metadata_lock_path, options_data_path, order_data_path};
+use crate::stores::{read_versioned, write_versioned, Migration, VersionedStore, INITIAL_MIGRATIONS};
+
+// === PROFILE METADATA ===
+
+// Everything we store about profiles, kept in a single file so related changes are written together
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct Metadata {
+    // Avatar of each profile
+    #[serde(default)]
+    pub avatars: HashMap<String, String>,
+    #[serde(default)]
+    pub profile_options: HashMap<String, HashMap<String, Value>>,
+    #[serde(default)]
+    pub global_options: HashMap<String, Value>,
+    // Profile IDs in the order the user arranged them
+    #[serde(default)]
+    pub order: Vec<String>
+}
+
+impl VersionedStore for Metadata {
+    const DESCRIPTION: &'static str = "profile metadata";
+    const MIGRATIONS: &'static [Migration] = INITIAL_MIGRATIONS;
+}
+
+// Serializes access to the metadata between all running instances
+fn lock_metadata(config_dir: &Path, exclusive: bool) -> eyre::Result<File> {
+    let lock_file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .open(metadata_lock_path(config_dir))
+        .context("failed to open metadata lock")?;
+    if exclusive {
+        lock_file.lock_exclusive()
+    } else {
+        lock_file.lock_shared()
+    }.context("failed to lock metadata")?;
+    Ok(lock_file)
+}
+
+pub fn read_metadata(config_dir: &Path) -> Metadata {
+    // Reading without the lock could only observe a complete old or new file, the lock just
+    // avoids reading while an import is half done
+    let _lock = lock_metadata(config_dir, false)
+        .map_err(|e| log::warn!("Reading metadata without lock: {:?}", e));
+    read_versioned(&metadata_data_path(config_dir))
+}
+
+/// Change the metadata in a single transaction. The change is only written if `update` succeeds
+/// and other instances can't change the metadata in the meantime.
+pub fn update_metadata<R, F>(config_dir: &Path, update: F) -> eyre::Result<R>
+    where F: FnOnce(&mut Metadata) -> eyre::Result<R> {
+    let _lock = lock_metadata(config_dir, true)?;
+    let mut metadata: Metadata = read_versioned(&metadata_data_path(config_dir));
+    let result = update(&mut metadata)?;
+    write_versioned(&metadata_data_path(config_dir), &metadata)?;
+    Ok(result)
+}
+
+/// Move the metadata of connectors that kept it in separate files into the metadata store. The
+/// imported files are kept with an `.imported-<timestamp>` suffix.
+pub fn import_legacy_metadata(config_dir: &Path) {
+    let legacy_paths = [
+        avatar_data_path(config_dir),
+        options_data_path(config_dir),
+        global_options_data_path(config_dir),
+        order_data_path(config_dir)
+    ];
+    if !legacy_paths.iter().any(|p| p.exists()) {
+        return;
+    }
+
+    let result = update_metadata(config_dir, |metadata| {
+        // Another instance may have imported them while we were waiting for the lock
+        if metadata_data_path(config_dir).exists() {
+            return Ok(None);
+        }
+        let imported: Vec<&PathBuf> = legacy_paths.iter().filter(|p| p.exists()).collect();
+        metadata.avatars = read_versioned::<AvatarData>(&avatar_data_path(config_dir)).avatars;
+        metadata.profile_options = read_versioned::<OptionsData>(&options_data_path(config_dir)).options;
+        metadata.global_options = read_versioned::<GlobalOptionsData>(&global_options_data_path(config_dir)).0;
+        metadata.order = read_versioned::<OrderData>(&order_data_path(config_dir)).order;
+        Ok(Some(imported))
+    });
+
+    let imported = match result {
+        Ok(Some(imported)) => {
+            log::info!("Imported profile metadata from the legacy stores: {:?}", imported);
+            imported
+        }
+        Ok(None) => {
+            // An older connector may still be writing them, they are not ours to move
+            log::warn!("Ignoring legacy metadata stores, the metadata store already exists");
+            return;
+        }
+        Err(e) => {
+            log::error!("Failed to import legacy profile metadata: {:?}", e);
+            return;
+        }
+    };
+
+    let suffix = format!(".imported-{}", chrono::Local::now().format("%Y%m%d-%H%M%S"));
+    for path in imported {
+        // Unreadable stores were quarantined instead
+        if !path.exists() {
+            continue;
+        }
+        let imported_path = match unused_path(path, &suffix) {
+            Some(p) => p,
+            None => {
+                log::error!("Not renaming imported store {:?}, no unused name is left", path);
+                continue;
+            }
+        };
+        if let Err(e) = fs::rename(path, &imported_path) {
+            log::error!("Failed to rename imported store {:?}: {:?}", path, e);
+        }
+    }
+}
+
+// Earlier imports must never be overwritten
+fn unused_path(path: &Path, suffix: &str) -> Option<PathBuf> {
+    (0..100).map(|n| {
+        let mut candidate = path.as_os_str().to_owned();
+        candidate.push(suffix);
+        if n > 0 {
+            candidate.push(format!("-{}", n));
+        }
+        PathBuf::from(candidate)
+    }).find(|candidate| !candidate.exists())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use serde_json::json;
+    use crate::storage::{avatar_data_path, global_options_data_path, metadata_data_path, options_data_path, order_data_path};
+    use super::{import_legacy_metadata, read_metadata, update_metadata};
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("fps-test-metadata-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write_legacy_stores(dir: &Path) {
+        fs::write(avatar_data_path(dir), json!({ "avatars": { "a": "avatar-a" } }).to_string()).unwrap();
+        fs::write(options_data_path(dir), json!({ "options": { "a": { "theme": "dark" } } }).to_string()).unwrap();
+        fs::write(global_options_data_path(dir), json!({ "windowFocusWorkaround": true }).to_string()).unwrap();
+        fs::write(order_data_path(dir), json!({ "order": ["b", "a"] }).to_string()).unwrap();
+    }
+
+    fn imported_files(dir: &Path) -> Vec<String> {
+        let mut files: Vec<String> = fs::read_dir(dir).unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|name| name.contains(".imported-"))
+            .collect();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn legacy_stores_are_imported() {
+        let dir = test_dir("import");
+        write_legacy_stores(&dir);
+
+        import_legacy_metadata(&dir);
+
+        let metadata = read_metadata(&dir);
+        assert_eq!(metadata.avatars["a"], "avatar-a");
+        assert_eq!(metadata.profile_options["a"]["theme"], json!("dark"));
+        assert_eq!(metadata.global_options["windowFocusWorkaround"], json!(true));
+        assert_eq!(metadata.order, vec!["b".to_owned(), "a".to_owned()]);
+
+        assert!(!avatar_data_path(&dir).exists());
+        assert!(!options_data_path(&dir).exists());
+        assert!(!global_options_data_path(&dir).exists());
+        assert!(!order_data_path(&dir).exists());
+        let imported = imported_files(&dir);
+        assert_eq!(imported.len(), 4);
+        for prefix in &["avatars.json", "global-options.json", "profile-options.json", "profile-order.json"] {
+            assert!(imported.iter().any(|name| name.starts_with(&format!("{}.imported-", prefix))));
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn second_import_does_nothing() {
+        let dir = test_dir("import-twice");
+        write_legacy_stores(&dir);
+        import_legacy_metadata(&dir);
+        update_metadata(&dir, |metadata| {
+            metadata.order = vec!["a".to_owned(), "b".to_owned()];
+            Ok(())
+        }).unwrap();
+        let metadata_before = fs::read(metadata_data_path(&dir)).unwrap();
+
+        // An older connector that is still running writes its own store again
+        write_legacy_stores(&dir);
+        import_legacy_metadata(&dir);
+
+        assert_eq!(fs::read(metadata_data_path(&dir)).unwrap(), metadata_before);
+        assert!(avatar_data_path(&dir).exists());
+        assert!(order_data_path(&dir).exists());
+        assert_eq!(imported_files(&dir).len(), 4);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
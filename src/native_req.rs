@@ -1,9 +1,12 @@
 use std::collections::HashMap;
 use serde_json::Value;
 use std::io::Read;
+use std::path::PathBuf;
 use byteorder::{ReadBytesExt, NativeEndian};
 use eyre::Context;
 use serde::{Deserialize, Serialize};
+use crate::routing::RoutingRule;
+use crate::workspaces::Workspace;
 
 // === NATIVE REQUEST ===
 #[derive(Serialize, Deserialize, Debug)]
@@ -16,14 +19,19 @@ pub struct NativeMessageInitialize {
 #[derive(Serialize, Deserialize, Debug)]
 pub struct NativeMessageLaunchProfile {
     pub profile_id: String,
-    pub url: Option<String>
+    pub url: Option<String>,
+    #[serde(default)]
+    pub new_window: bool
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct NativeMessageCreateProfile {
     pub name: String,
     pub avatar: String,
-    pub options: HashMap<String, Value>
+    pub options: HashMap<String, Value>,
+    // Extra profile root to create the profile in, the primary root if missing
+    #[serde(default)]
+    pub root_id: Option<String>
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -60,6 +68,40 @@ pub struct NativeMessageUpdateProfileOrder {
     pub order: Vec<String>,
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+pub struct NativeMessageOpenUrl {
+    pub url: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct NativeMessageUpdateRoutingRules {
+    pub rules: Vec<RoutingRule>,
+    #[serde(default)]
+    pub fallback_profile_id: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct NativeMessageUpdateWorkspaces {
+    pub workspaces: Vec<Workspace>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct NativeMessageLaunchWorkspace {
+    pub workspace_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct NativeMessageUpdateConnectorConfig {
+    // None to detect them automatically
+    pub browser_profile_dir: Option<PathBuf>,
+    pub browser_binary: Option<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct NativeMessageDismissDiagnostic {
+    pub store: String,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(tag = "command")]
 pub enum NativeMessage {
@@ -74,6 +116,22 @@ pub enum NativeMessage {
     GetAvatar(NativeMessageGetAvatar),
     DeleteAvatar(NativeMessageDeleteAvatar),
     UpdateProfileOrder(NativeMessageUpdateProfileOrder),
+    ListRunningProfiles,
+    OpenUrl(NativeMessageOpenUrl),
+    GetRoutingRules,
+    UpdateRoutingRules(NativeMessageUpdateRoutingRules),
+    GetWorkspaces,
+    UpdateWorkspaces(NativeMessageUpdateWorkspaces),
+    LaunchWorkspace(NativeMessageLaunchWorkspace),
+    ListBrowsers,
+    ListProfileRoots,
+    GetConnectorConfig,
+    UpdateConnectorConfig(NativeMessageUpdateConnectorConfig),
+    // Initialize again without restarting, e.g. after the extension was upgraded
+    Reinitialize(NativeMessageInitialize),
+    ReloadConfig,
+    GetDiagnostics,
+    DismissDiagnostic(NativeMessageDismissDiagnostic),
 }
 
 #[derive(Serialize, Deserialize, Debug)]
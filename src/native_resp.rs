@@ -7,7 +7,15 @@ use crate::profiles::ProfileEntry;
 use std::io;
 use byteorder::{NativeEndian, WriteBytesExt};
 use std::io::Write;
+use std::time::UNIX_EPOCH;
 use serde::{Serialize};
+use crate::process_registry::RunningProfile;
+use crate::routing::RoutingRule;
+use crate::workspaces::Workspace;
+use crate::browsers::{BrowserChannel, InstallType, InstalledBrowser};
+use crate::config::Config;
+use crate::diagnostics::StoreDiagnostic;
+use crate::profile_root::{ProfileRootResolution, ProfileRootSource};
 
 #[derive(Serialize, Debug)]
 #[serde(untagged)]
@@ -81,7 +89,10 @@ pub struct NativeResponseProfileListProfileEntry {
     pub name: String,
     pub default: bool,
     pub avatar: Option<String>,
-    pub options: HashMap<String, Value>
+    pub options: HashMap<String, Value>,
+    // Only set for profiles of extra profile roots
+    pub root_id: Option<String>,
+    pub browser: Option<String>
 }
 
 impl NativeResponseProfileListProfileEntry {
@@ -91,7 +102,102 @@ impl NativeResponseProfileListProfileEntry {
             name: entry.name.clone(),
             default: entry.default,
             avatar: entry.avatar.clone(),
-            options: entry.options.clone()
+            options: entry.options.clone(),
+            root_id: entry.root_id.clone(),
+            browser: entry.browser.clone()
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct NativeResponseRunningProfile {
+    pub profile_id: String,
+    pub pid: Option<u32>,
+    // Milliseconds since the UNIX epoch
+    pub started_at: Option<u64>,
+    pub binary: Option<String>,
+    pub launched_by_connector: bool
+}
+
+impl NativeResponseRunningProfile {
+    pub fn from_running_profile(running: &RunningProfile) -> NativeResponseRunningProfile {
+        NativeResponseRunningProfile {
+            profile_id: running.profile_id.clone(),
+            pid: running.pid,
+            started_at: running.started_at
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_millis() as u64),
+            binary: running.binary.as_ref().map(|b| b.to_string_lossy().to_string()),
+            launched_by_connector: running.launched_by_connector
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct NativeResponseBrowser {
+    pub product_name: String,
+    pub version: Option<String>,
+    pub channel: BrowserChannel,
+    pub install_type: InstallType,
+    pub binary: String,
+    pub install_dir: Option<String>,
+    pub desktop_entry: Option<String>
+}
+
+impl NativeResponseBrowser {
+    pub fn from_installed_browser(browser: &InstalledBrowser) -> NativeResponseBrowser {
+        NativeResponseBrowser {
+            product_name: browser.product_name.clone(),
+            version: browser.version.clone(),
+            channel: browser.channel,
+            install_type: browser.install_type,
+            binary: browser.binary.to_string_lossy().to_string(),
+            install_dir: browser.install_dir.as_ref().map(|d| d.to_string_lossy().to_string()),
+            desktop_entry: browser.desktop_entry.as_ref().map(|d| d.to_string_lossy().to_string())
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct NativeResponseProfileRootEntry {
+    // Missing for the primary root
+    pub id: Option<String>,
+    pub name: Option<String>,
+    pub profile_dir: String
+}
+
+#[derive(Serialize, Debug)]
+pub struct NativeResponseConnectorConfig {
+    // As configured, None means detected automatically
+    pub browser_profile_dir: Option<String>,
+    pub browser_binary: Option<String>,
+    // The profile dir actually in use
+    pub effective_browser_profile_dir: String
+}
+
+impl NativeResponseConnectorConfig {
+    pub fn from_config(config: &Config) -> NativeResponseConnectorConfig {
+        NativeResponseConnectorConfig {
+            browser_profile_dir: config.configured_browser_profile_dir().map(|d| d.to_string_lossy().to_string()),
+            browser_binary: config.configured_browser_binary().map(|b| b.to_string_lossy().to_string()),
+            effective_browser_profile_dir: config.browser_profile_dir().to_string_lossy().to_string()
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct NativeResponseProfileRoot {
+    pub path: String,
+    pub source: ProfileRootSource,
+    pub steps: Vec<String>
+}
+
+impl NativeResponseProfileRoot {
+    pub fn from_resolution(resolution: ProfileRootResolution) -> NativeResponseProfileRoot {
+        NativeResponseProfileRoot {
+            path: resolution.root.to_string_lossy().to_string(),
+            source: resolution.source,
+            steps: resolution.steps
         }
     }
 }
@@ -100,7 +206,9 @@ impl NativeResponseProfileListProfileEntry {
 #[serde(untagged)]
 pub enum NativeResponseData {
     Initialized {
-        cached: bool
+        cached: bool,
+        // How the connector decided which browser's profiles to manage
+        profile_root: NativeResponseProfileRoot
     },
     ProfileLaunched,
     ProfileCreated {
@@ -118,18 +226,41 @@ pub enum NativeResponseData {
     GetAvatarResult { data: String, mime: String },
     AvatarDeleted,
     ProfileOrderUpdated,
+    RunningProfiles { profiles: Vec<NativeResponseRunningProfile> },
+    // profile_id is missing if no rule matched the URL
+    UrlOpened { profile_id: Option<String> },
+    RoutingRules { rules: Vec<RoutingRule>, fallback_profile_id: Option<String> },
+    ProfileList { profiles: Vec<NativeResponseProfileListProfileEntry> },
+    Workspaces { workspaces: Vec<Workspace> },
+    WorkspaceLaunched,
+    Browsers { browsers: Vec<NativeResponseBrowser> },
+    ProfileRoots { roots: Vec<NativeResponseProfileRootEntry> },
+    ConnectorConfig { config: NativeResponseConnectorConfig },
+    Diagnostics { diagnostics: Vec<StoreDiagnostic> },
 }
 
 #[derive(Serialize, Debug)]
 #[serde(tag = "event")]
 pub enum NativeResponseEvent {
     ProfileList { current_profile_id: String, profiles: Vec<NativeResponseProfileListProfileEntry> },
-    FocusWindow { url: Option<String> },
+    FocusWindow { url: Option<String>, new_window: bool },
     CloseManager,
-    ConnectorInformation { version: String },
+    ConnectorInformation {
+        version: String,
+        config_dir: String,
+        data_dir: String,
+        // Missing unless overridden, the profile root is detected once the connector is initialized
+        profile_root: Option<String>
+    },
     OptionsUpdated { options: HashMap<String, Value> },
     AvatarsUpdated { avatars: Vec<String> },
     ProfileOrderUpdated { order: Vec<String> },
+    ProfileRunningStateChanged { profile_id: String, running: bool, pid: Option<u32> },
+    RoutingRulesUpdated { rules: Vec<RoutingRule>, fallback_profile_id: Option<String> },
+    WorkspacesUpdated { workspaces: Vec<Workspace> },
+    ConnectorConfigUpdated { config: NativeResponseConnectorConfig },
+    // Stores that could not be read and were quarantined
+    Diagnostics { diagnostics: Vec<StoreDiagnostic> },
 }
 
 pub fn write_native_response(resp: NativeResponseWrapper) {
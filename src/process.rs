@@ -1,16 +1,19 @@
 use std::{io, env, fs};
 use std::env::VarError;
 use cfg_if::cfg_if;
-use std::path::PathBuf;
-use std::process::{exit, Child, Command, Stdio};
+use std::path::{Component, Path, PathBuf};
+use std::process::{Child, Command, Stdio};
 use once_cell::sync::Lazy;
 use crate::state::AppState;
 use crate::profiles::ProfileEntry;
+use crate::storage::{launch_log_path, resolve_config_path};
+use crate::process_registry::is_profile_running;
+use crate::browsers::{discover_browsers, find_browser, is_browser_binary, InstalledBrowser};
+use crate::desktop_entries::LaunchTemplate;
 
 cfg_if! {
     if #[cfg(target_family = "unix")] {
-        use nix::unistd::ForkResult;
-        use nix::sys::wait::waitpid;
+        use std::os::unix::process::CommandExt;
     } else if #[cfg(target_family = "windows")] {
         use windows::Win32::System::Threading as win_threading;
         use windows::Win32::UI::Shell::{ApplicationActivationManager, IApplicationActivationManager, AO_NONE};
@@ -27,7 +30,6 @@ cfg_if! {
 #[derive(Debug)]
 pub enum ForkBrowserProcError {
     BadExitCode,
-    ForkError { error_message: String },
     ProcessLaunchError(io::Error),
     MSIXProcessLaunchError { error_message: String },
     BinaryNotFound,
@@ -35,114 +37,21 @@ pub enum ForkBrowserProcError {
     COMError { error_message: String }
 }
 
-// List of known browser executable names
-const BROWSER_EXECUTABLES: [&str; 4] = ["firefox", "librewolf", "waterfox", "zen-browser"];
-
-// Flatpak app IDs and binary paths for supported browsers
-const FLATPAK_BROWSERS: [(&str, &str, &str); 4] = [
-    ("firefox", "org.mozilla.firefox", "/var/lib/flatpak/app/org.mozilla.firefox/current/active/files/bin/firefox"),
-    ("librewolf", "io.gitlab.librewolf-community", "/var/lib/flatpak/app/io.gitlab.librewolf-community/current/active/files/bin/librewolf"),
-    ("waterfox", "net.waterfox.waterfox", "/var/lib/flatpak/app/net.waterfox.waterfox/current/active/files/bin/waterfox"),
-    ("zen-browser", "org.mozilla.firefox.zen", "/var/lib/flatpak/app/org.mozilla.firefox.zen/current/active/files/bin/firefox")
-];
-
-// Find browser binary by looking in common locations
-fn find_browser_binary() -> Option<PathBuf> {
-    cfg_if! {
-        if #[cfg(target_family = "unix")] {
-            // Check home directory for user Flatpak installations
-            if let Ok(home_dir) = env::var("HOME") {
-                for (name, app_id, bin_path) in FLATPAK_BROWSERS.iter() {
-                    // Check user Flatpak installation
-                    let user_flatpak_path = format!("{}/.local/share/flatpak/app/{}/current/active/files/bin/{}", 
-                                                  home_dir, app_id, name);
-                    let user_path_buf = PathBuf::from(&user_flatpak_path);
-                    if user_path_buf.exists() {
-                        log::info!("Found user Flatpak {} at: {}", name, user_flatpak_path);
-                        return Some(user_path_buf);
-                    }
-                    
-                    // Check system Flatpak installation
-                    let system_path_buf = PathBuf::from(bin_path);
-                    if system_path_buf.exists() {
-                        log::info!("Found system Flatpak {} at: {}", name, bin_path);
-                        return Some(system_path_buf);
-                    }
-                }
-            }
-            
-            // Check common paths on Linux
-            for browser in BROWSER_EXECUTABLES.iter() {
-                // Check standard locations
-                let standard_paths = [
-                    format!("/usr/bin/{}", browser),
-                    format!("/usr/local/bin/{}", browser),
-                    format!("/snap/bin/{}", browser),
-                ];
-                
-                for path in standard_paths.iter() {
-                    let path_buf = PathBuf::from(path);
-                    if path_buf.exists() {
-                        log::info!("Found browser binary at: {}", path);
-                        return Some(path_buf);
-                    }
-                }
-            }
-        } else if #[cfg(target_os = "macos")] {
-            // Check common paths on macOS
-            let browser_paths = [
-                "/Applications/Firefox.app/Contents/MacOS/firefox",
-                "/Applications/LibreWolf.app/Contents/MacOS/librewolf",
-                "/Applications/Waterfox.app/Contents/MacOS/waterfox",
-                "/Applications/Zen Browser.app/Contents/MacOS/firefox",
-                // Also check user Applications folder
-                &format!("{}/Applications/Firefox.app/Contents/MacOS/firefox", env::var("HOME").unwrap_or_default()),
-                &format!("{}/Applications/LibreWolf.app/Contents/MacOS/librewolf", env::var("HOME").unwrap_or_default()),
-                &format!("{}/Applications/Waterfox.app/Contents/MacOS/waterfox", env::var("HOME").unwrap_or_default()),
-                &format!("{}/Applications/Zen Browser.app/Contents/MacOS/firefox", env::var("HOME").unwrap_or_default()),
-            ];
-            
-            for path in browser_paths.iter() {
-                let path_buf = PathBuf::from(path);
-                if path_buf.exists() {
-                    log::info!("Found browser binary at: {}", path);
-                    return Some(path_buf);
-                }
-            }
-        } else if #[cfg(target_os = "windows")] {
-            // Check common paths on Windows
-            let program_files = env::var("ProgramFiles").unwrap_or_else(|_| String::from("C:\\Program Files"));
-            let program_files_x86 = env::var("ProgramFiles(x86)").unwrap_or_else(|_| String::from("C:\\Program Files (x86)"));
-            
-            let browser_paths = [
-                format!("{}\\Mozilla Firefox\\firefox.exe", program_files),
-                format!("{}\\LibreWolf\\librewolf.exe", program_files),
-                format!("{}\\Waterfox\\waterfox.exe", program_files),
-                format!("{}\\Zen Browser\\firefox.exe", program_files),
-                format!("{}\\Mozilla Firefox\\firefox.exe", program_files_x86),
-                format!("{}\\LibreWolf\\librewolf.exe", program_files_x86),
-                format!("{}\\Waterfox\\waterfox.exe", program_files_x86),
-                format!("{}\\Zen Browser\\firefox.exe", program_files_x86),
-            ];
-            
-            for path in browser_paths.iter() {
-                let path_buf = PathBuf::from(path);
-                if path_buf.exists() {
-                    log::info!("Found browser binary at: {}", path);
-                    return Some(path_buf);
-                }
-            }
-        }
-    }
-    
-    None
+// A browser process started by us, MSIX launches do not give us one
+pub struct SpawnedBrowser {
+    pub binary: PathBuf,
+    pub child: Child,
+    // Where the stderr of the browser is being written to
+    pub stderr_log: Option<PathBuf>
 }
 
-pub fn fork_browser_proc(app_state: &AppState, profile: &ProfileEntry, url: Option<String>) -> Result<(), ForkBrowserProcError> {
+pub fn fork_browser_proc(app_state: &AppState, profile: &ProfileEntry, url: Option<String>, new_window: bool) -> Result<Option<SpawnedBrowser>, ForkBrowserProcError> {
     // Special case on Windows when FF is installed from Microsoft Store
     cfg_if! {
         if #[cfg(target_family = "windows")] {
-            if let Ok(msix_package) = get_msix_package() {
+            // Profiles of extra profile roots belong to another browser
+            let msix_package = get_msix_package().ok().filter(|_| profile.root_id.is_none());
+            if let Some(msix_package) = msix_package {
                 let aam: IApplicationActivationManager = unsafe {
                     CoCreateInstance(
                         &ApplicationActivationManager,
@@ -153,7 +62,8 @@ pub fn fork_browser_proc(app_state: &AppState, profile: &ProfileEntry, url: Opti
                     error_message: e.message().to_string_lossy()
                 })?;
 
-                let browser_args = build_browser_args(&profile.name, url)
+                // Paths are virtualized inside MSIX packages, so select the profile by its name instead
+                let browser_args = build_browser_args(&ProfileSelector::Name(&profile.name), url, new_window)
                     .iter()
                     // Surround each arg with quotes and escape quotes with triple quotes
                     // See: https://stackoverflow.com/questions/7760545/escape-double-quotes-in-parameter
@@ -174,115 +84,359 @@ pub fn fork_browser_proc(app_state: &AppState, profile: &ProfileEntry, url: Opti
                     error_message: e.message().to_string_lossy()
                 })?;
 
-                return Ok(());
+                return Ok(None);
             }
         }
     }
 
+    // Profiles of extra profile roots are launched with the browser of their root
+    if let Some(root_id) = &profile.root_id {
+        let (binary, backend) = find_root_browser(app_state, root_id)
+            .ok_or(ForkBrowserProcError::BinaryNotFound)?;
+        log::trace!("Browser launch backend for profile root {}: {:?}", root_id, backend);
+        let browser_args = build_browser_args(&ProfileSelector::for_profile(app_state, profile), url, new_window);
+        let stderr_log = launch_log_path(&app_state.data_dir, &profile.id);
+        return launch_browser_process(&backend, browser_args, &stderr_log)
+            .map(|(child, stderr_log)| Some(SpawnedBrowser { binary, child, stderr_log }));
+    }
+
     // Try to get browser binary from various sources
-    let parent_proc = match app_state.config.browser_binary() {
-        Some(v) => v.clone(),
-        None => match get_parent_proc_path() {
-            Ok(v) => v.clone(),
-            Err(_) => match find_browser_binary() {
-                Some(binary) => binary,
+    let parent_proc = app_state.config.browser_binary()
+        .or_else(|| get_parent_proc_path().ok().cloned());
+
+    let (binary, backend) = match parent_proc {
+        Some(parent_proc) if parent_proc.exists() => {
+            log::trace!("Browser binary found: {:?}", parent_proc);
+            let backend = LaunchBackend::for_binary(&parent_proc);
+            (parent_proc, backend)
+        }
+        parent_proc => {
+            // Try to find an alternative browser if the original one doesn't exist
+            let browser = match find_browser() {
+                Some(b) => b,
+                None if parent_proc.is_some() => return Err(ForkBrowserProcError::BinaryDoesNotExist),
                 None => return Err(ForkBrowserProcError::BinaryNotFound)
-            }
+            };
+            log::info!("Original browser binary not found, using alternative: {:?}", browser.binary);
+            let backend = LaunchBackend::for_browser(&browser);
+            (browser.binary, backend)
         }
     };
 
-    if !parent_proc.exists() {
-        // Try to find an alternative browser if the original one doesn't exist
-        match find_browser_binary() {
-            Some(alt_binary) => {
-                log::info!("Original browser binary not found, using alternative: {:?}", alt_binary);
-                if !alt_binary.exists() {
-                    return Err(ForkBrowserProcError::BinaryDoesNotExist);
-                }
-                
-                let browser_args = build_browser_args(&profile.name, url);
-                log::trace!("Browser args: {:?}", browser_args);
-                
-                return launch_browser_process(&alt_binary, browser_args);
+    log::trace!("Browser launch backend: {:?}", backend);
+
+    let browser_args = build_browser_args(&ProfileSelector::for_profile(app_state, profile), url, new_window);
+
+    log::trace!("Browser args: {:?}", browser_args);
+    
+    let stderr_log = launch_log_path(&app_state.data_dir, &profile.id);
+    launch_browser_process(&backend, browser_args, &stderr_log)
+        .map(|(child, stderr_log)| Some(SpawnedBrowser { binary, child, stderr_log }))
+}
+
+// Find the browser owning an extra profile root, either configured or installed under the name of the root
+fn find_root_browser(app_state: &AppState, root_id: &str) -> Option<(PathBuf, LaunchBackend)> {
+    let root = app_state.config.extra_profile_root(root_id)?;
+    if let Some(binary) = root.browser_binary.as_deref().map(resolve_config_path).filter(|b| b.exists()) {
+        let backend = LaunchBackend::for_binary(&binary);
+        return Some((binary, backend));
+    }
+
+    let browser = discover_browsers().into_iter().find(|b| {
+        let binary_name = b.binary.file_stem().and_then(|n| n.to_str()).unwrap_or_default();
+        binary_name.eq_ignore_ascii_case(&root.id) || b.product_name.eq_ignore_ascii_case(&root.id)
+    });
+    if browser.is_none() {
+        log::warn!("Could not find the browser of profile root {}", root_id);
+    }
+    browser.map(|b| {
+        let backend = LaunchBackend::for_browser(&b);
+        (b.binary, backend)
+    })
+}
+
+// Which Flatpak installation an app was found in
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FlatpakInstallation {
+    User,
+    System
+}
+
+// How a browser should be started
+#[derive(Clone, Debug, PartialEq)]
+pub enum LaunchBackend {
+    // Execute the browser binary directly
+    Direct(PathBuf),
+    // Binaries inside a Flatpak can't be executed outside of the sandbox, go through `flatpak run` instead
+    Flatpak {
+        app_id: String,
+        branch: Option<String>,
+        arch: Option<String>,
+        installation: Option<FlatpakInstallation>
+    },
+    // Binaries inside a snap must be started through the wrapper in /snap/bin to get confined properly
+    Snap { name: String },
+    // Start the browser the same way the desktop does
+    Template(LaunchTemplate)
+}
+
+const FLATPAK_INFO_PATH: &str = "/.flatpak-info";
+const SNAP_BIN_DIR: &str = "/snap/bin";
+
+impl LaunchBackend {
+    pub fn for_browser(browser: &InstalledBrowser) -> LaunchBackend {
+        match &browser.launch_template {
+            Some(template) => LaunchBackend::Template(template.clone()),
+            None => Self::for_binary(&browser.binary)
+        }
+    }
+
+    pub fn for_binary(path: &Path) -> LaunchBackend {
+        if let Some(backend) = Self::flatpak_for_binary(path) {
+            return backend;
+        }
+        if let Some(backend) = Self::snap_for_binary(path) {
+            return backend;
+        }
+        LaunchBackend::Direct(path.to_path_buf())
+    }
+
+    // Paths look like this: [~/.local/share|/var/lib]/flatpak/app/<app id>/<arch>/<branch>/active/files/bin/firefox
+    //   <arch>/<branch> may also be replaced by the "current" symlink
+    fn flatpak_for_binary(path: &Path) -> Option<LaunchBackend> {
+        // If we are inside the Flatpak, the browser binary will be somewhere in /app
+        if path.starts_with("/app") {
+            if let Some(backend) = Self::flatpak_for_current_sandbox() {
+                return Some(backend);
             }
-            None => return Err(ForkBrowserProcError::BinaryDoesNotExist)
         }
+
+        let components: Vec<&str> = path.components()
+            .filter_map(|c| match c {
+                Component::Normal(c) => c.to_str(),
+                _ => None
+            })
+            .collect();
+        let app_idx = components.windows(2)
+            .position(|w| w == ["flatpak", "app"])?
+            + 2;
+        let app_id = components.get(app_idx)?.to_string();
+        let app_dir = path.ancestors()
+            .find(|p| p.file_name().map_or(false, |n| n == app_id.as_str()))?;
+
+        let (arch, branch) = match components.get(app_idx + 1) {
+            Some(&"current") => match fs::read_link(app_dir.join("current")) {
+                // Symlink target looks like: x86_64/stable
+                Ok(target) => {
+                    let mut parts = target.iter().map(|p| p.to_string_lossy().to_string());
+                    (parts.next(), parts.next())
+                }
+                Err(e) => {
+                    log::warn!("Failed to resolve current Flatpak branch of {}: {:?}", app_id, e);
+                    (None, None)
+                }
+            },
+            Some(arch) => (Some(arch.to_string()), components.get(app_idx + 2).map(|b| b.to_string())),
+            None => (None, None)
+        };
+
+        let installation = directories::BaseDirs::new()
+            .map(|d| d.data_local_dir().join("flatpak"))
+            .map(|user_dir| if path.starts_with(user_dir) {
+                FlatpakInstallation::User
+            } else {
+                FlatpakInstallation::System
+            });
+
+        Some(LaunchBackend::Flatpak { app_id, branch, arch, installation })
+    }
+
+    // Build a Flatpak backend for the app we are currently sandboxed in (if any)
+    fn flatpak_for_current_sandbox() -> Option<LaunchBackend> {
+        let info = ini::Ini::load_from_file(FLATPAK_INFO_PATH).ok()?;
+        let app_id = info.get_from(Some("Application"), "name")?.to_owned();
+        let branch = info.get_from(Some("Instance"), "branch").map(str::to_owned);
+        let arch = info.get_from(Some("Instance"), "arch").map(str::to_owned);
+        let installation = info.get_from(Some("Instance"), "app-path")
+            .map(|p| if p.contains("/.local/share/flatpak/") {
+                FlatpakInstallation::User
+            } else {
+                FlatpakInstallation::System
+            });
+        Some(LaunchBackend::Flatpak { app_id, branch, arch, installation })
     }
 
-    log::trace!("Browser binary found: {:?}", parent_proc);
+    // Paths look like this: /snap/<name>/<revision>/usr/lib/firefox/firefox or /snap/bin/<name>
+    fn snap_for_binary(path: &Path) -> Option<LaunchBackend> {
+        let mut components = path.strip_prefix("/snap").ok()?.iter();
+        let name = match components.next()?.to_str()? {
+            "bin" => components.next()?.to_str()?,
+            name => name
+        };
+        Some(LaunchBackend::Snap { name: name.to_owned() })
+    }
 
-    let browser_args = build_browser_args(&profile.name, url);
+    fn build_command(&self, args: Vec<String>) -> Command {
+        self.build_command_from(args, running_in_flatpak())
+    }
 
-    log::trace!("Browser args: {:?}", browser_args);
-    
-    launch_browser_process(&parent_proc, browser_args)
+    // `in_flatpak` tells whether the connector runs inside a Flatpak sandbox
+    fn build_command_from(&self, args: Vec<String>, in_flatpak: bool) -> Command {
+        let args = match self {
+            LaunchBackend::Template(template) => template.expand(args),
+            _ => args
+        };
+        let (program, mut program_args): (PathBuf, Vec<String>) = match self {
+            LaunchBackend::Direct(path) => (path.clone(), Vec::new()),
+            LaunchBackend::Flatpak { app_id, branch, arch, installation } => {
+                let mut flatpak_args = vec!["run".to_owned()];
+                if let Some(branch) = branch {
+                    flatpak_args.push(format!("--branch={}", branch));
+                }
+                if let Some(arch) = arch {
+                    flatpak_args.push(format!("--arch={}", arch));
+                }
+                match installation {
+                    Some(FlatpakInstallation::User) => flatpak_args.push("--user".to_owned()),
+                    Some(FlatpakInstallation::System) => flatpak_args.push("--system".to_owned()),
+                    None => {}
+                }
+                flatpak_args.push(app_id.clone());
+                (PathBuf::from("flatpak"), flatpak_args)
+            }
+            LaunchBackend::Snap { name } => (Path::new(SNAP_BIN_DIR).join(name), Vec::new()),
+            LaunchBackend::Template(template) => (PathBuf::from(&template.program), Vec::new())
+        };
+        program_args.extend(args);
+
+        // Processes on the host can't be started from within the sandbox directly
+        let escape_sandbox = in_flatpak && match self {
+            LaunchBackend::Direct(path) => !path.starts_with("/app"),
+            _ => true
+        };
+        if escape_sandbox {
+            let mut command = Command::new("flatpak-spawn");
+            command.arg("--host").arg(program).args(program_args);
+            command
+        } else {
+            let mut command = Command::new(program);
+            command.args(program_args);
+            command
+        }
+    }
 }
 
-// Extract the process launching logic to a separate function
-fn launch_browser_process(browser_path: &PathBuf, args: Vec<String>) -> Result<(), ForkBrowserProcError> {
+// Check whether the connector itself is running inside a Flatpak sandbox
+pub fn running_in_flatpak() -> bool {
     cfg_if! {
-        if #[cfg(target_family = "unix")] {
-            match unsafe { nix::unistd::fork() } {
-                Ok(ForkResult::Parent {child}) => {
-                    match waitpid(child, None) {
-                        Ok(nix::sys::wait::WaitStatus::Exited(_, 0)) => Ok(()),
-                        _ => Err(ForkBrowserProcError::BadExitCode)
-                    }
-                },
-                Ok(ForkResult::Child) => exit(match nix::unistd::setsid() {
-                    Ok(_) => {
-                        // Close stdout, stderr and stdin
-                        /*unsafe {
-                            libc::close(0);
-                            libc::close(1);
-                            libc::close(2);
-                        }*/
-                        match spawn_browser_proc(browser_path, args) {
-                            Ok(_) => 0,
-                            Err(_) => 1
-                        }
-                    },
-                    Err(_) => 2
-                }),
-                Err(e) => Err(ForkBrowserProcError::ForkError { error_message: format!("{:?}", e) })
-            }
-        } else if #[cfg(target_family = "windows")] {
-            // TODO Change app ID to separate on taskbar?
-            match spawn_browser_proc(browser_path, args) {
-                Ok(_) => Ok(()),
-                Err(e) => Err(ForkBrowserProcError::ProcessLaunchError(e))
-            }
+        if #[cfg(target_os = "linux")] {
+            Path::new(FLATPAK_INFO_PATH).exists()
         } else {
-            compile_error!("Unknown OS!");
+            false
+        }
+    }
+}
+
+// Extract the process launching logic to a separate function
+fn launch_browser_process(backend: &LaunchBackend, args: Vec<String>, stderr_log: &Path) -> Result<(Child, Option<PathBuf>), ForkBrowserProcError> {
+    // Keep the output of the browser around so we can tell the user why it failed to start
+    let stderr = match open_launch_log(stderr_log) {
+        Ok(file) => Some(file),
+        Err(e) => {
+            log::warn!("Failed to open browser launch log {:?}, discarding browser output: {:?}", stderr_log, e);
+            None
+        }
+    };
+    let stderr_log = stderr.as_ref().map(|_| stderr_log.to_path_buf());
+
+    // TODO Change app ID to separate on taskbar?
+    spawn_browser_proc(backend, args, stderr)
+        .map(|child| (child, stderr_log))
+        .map_err(ForkBrowserProcError::ProcessLaunchError)
+}
+
+fn open_launch_log(path: &Path) -> io::Result<fs::File> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::OpenOptions::new()
+        .create(true)
+        .truncate(true)
+        .write(true)
+        .open(path)
+}
+
+// How the profile to launch is passed to the browser
+enum ProfileSelector<'a> {
+    // Select the profile directory directly, this does not depend on profiles.ini at all
+    Path { path: String, running: bool },
+    // Select the profile by name, the browser will look it up in profiles.ini
+    Name(&'a str)
+}
+
+impl<'a> ProfileSelector<'a> {
+    fn for_profile(app_state: &AppState, profile: &'a ProfileEntry) -> ProfileSelector<'a> {
+        let full_path = profile.full_path(&app_state.config);
+        // The browser would create a brand new profile if the directory is missing, let it report the error instead
+        if full_path.is_dir() {
+            if let Some(path) = full_path.to_str() {
+                return ProfileSelector::Path {
+                    path: path.to_owned(),
+                    running: is_profile_running(&full_path)
+                };
+            }
         }
+        log::trace!("Cannot launch profile {} by path, falling back to name: {:?}", profile.id, full_path);
+        ProfileSelector::Name(&profile.name)
     }
 }
 
-fn build_browser_args(profile_name: &str, url: Option<String>) -> Vec<String> {
-    let mut vec = vec![
-        "-P".to_owned(),
-        profile_name.to_owned()
-    ];
+fn build_browser_args(profile: &ProfileSelector, url: Option<String>, new_window: bool) -> Vec<String> {
+    let mut vec = match profile {
+        ProfileSelector::Path { path, running } => {
+            let mut vec = vec![
+                "--profile".to_owned(),
+                path.clone()
+            ];
+            // If the profile is not open, make sure the browser doesn't hand the request to an instance
+            //   running another profile. We can't use --no-remote here as the new instance would then
+            //   never receive any URLs we send it later on.
+            if !running {
+                vec.push("--new-instance".to_owned());
+            }
+            vec
+        }
+        ProfileSelector::Name(name) => vec![
+            "-P".to_owned(),
+            name.to_string()
+        ]
+    };
     if let Some(url) = url {
-        vec.push("--new-tab".to_owned());
+        vec.push(if new_window { "--new-window" } else { "--new-tab" }.to_owned());
         vec.push(url);
     }
     vec
 }
 
-fn spawn_browser_proc(bin_path: &PathBuf, args: Vec<String>) -> io::Result<Child> {
-    let mut command = Command::new(bin_path);
+fn spawn_browser_proc(backend: &LaunchBackend, args: Vec<String>, stderr: Option<fs::File>) -> io::Result<Child> {
+    let mut command = backend.build_command(args);
     cfg_if! {
-        if #[cfg(target_family = "windows")] {
+        if #[cfg(target_family = "unix")] {
+            // Start the browser in its own session so it outlives us
+            unsafe {
+                command.pre_exec(|| nix::unistd::setsid()
+                    .map(|_| ())
+                    .map_err(|e| io::Error::from_raw_os_error(e as i32)));
+            }
+        } else if #[cfg(target_family = "windows")] {
             command.creation_flags((win_threading::DETACHED_PROCESS | win_threading::CREATE_BREAKAWAY_FROM_JOB).0);
         }
     }
-    command.args(args);
     log::trace!("Executing command: {:?}", command);
     return command
         .stdin(Stdio::null())
         .stdout(Stdio::null())
-        .stderr(Stdio::null())
+        .stderr(stderr.map(Stdio::from).unwrap_or_else(Stdio::null))
         .spawn();
 }
 
@@ -292,31 +446,229 @@ pub enum GetParentProcError {
     LinuxOpenCurProcFailed(io::Error),
     LinuxFailedToParsePidString(String),
     LinuxCouldNotFindPPid,
-    LinuxResolveParentExeFailed(io::Error)
+    LinuxResolveParentExeFailed(io::Error),
+    LinuxNoBrowserAncestor
 }
 
-static PARENT_PROC: Lazy<Result<PathBuf, GetParentProcError>> = Lazy::new(|| {
-    // Get browser binary by reading crash-reporter env var
+// The browser that started us
+#[derive(Debug)]
+pub struct ParentProc {
+    pub path: PathBuf,
+    // The binary was replaced (e.g. by an update) after the browser started
+    pub deleted: bool
+}
+
+// How many ancestors are searched for the browser, we may be started through wrappers
+#[cfg(target_os = "linux")]
+const MAX_PARENT_PROC_DEPTH: usize = 8;
+#[cfg(target_os = "linux")]
+const DELETED_EXE_SUFFIX: &str = " (deleted)";
+
+static PARENT_PROC: Lazy<Result<ParentProc, GetParentProcError>> = Lazy::new(|| {
+    // Get browser binary by reading crash-reporter env var, launches fall back to the installed
+    //   browsers if it is missing
     let crash_reporter_result = env::var("MOZ_CRASHREPORTER_RESTART_ARG_0")
-        .map(PathBuf::from)
+        .map(|path| ParentProc { path: PathBuf::from(path), deleted: false })
         .map_err(GetParentProcError::NoCrashReporterEnvVar);
-    
-    // If crash reporter env var is available, use it
-    if let Ok(path) = &crash_reporter_result {
-        if path.exists() {
-            return crash_reporter_result;
+
+    cfg_if! {
+        if #[cfg(target_os = "linux")] {
+            if crash_reporter_result.is_err() {
+                let proc_result = find_parent_proc_linux();
+                log::trace!("Crash reporter env var missing, parent process from /proc: {:?}", proc_result);
+                return proc_result;
+            }
         }
     }
-    
-    // Otherwise, try to find a browser binary
-    if let Some(browser_path) = find_browser_binary() {
-        Ok(browser_path)
-    } else {
-        // If no browser binary found, return the original result
-        crash_reporter_result
-    }
+
+    crash_reporter_result
 });
 
-pub fn get_parent_proc_path() -> Result<&'static PathBuf, &'static GetParentProcError> {
+// Walk up the process tree until we find the main process of the browser
+#[cfg(target_os = "linux")]
+fn find_parent_proc_linux() -> Result<ParentProc, GetParentProcError> {
+    let mut pid = read_linux_ppid("self")?;
+    for _ in 0..MAX_PARENT_PROC_DEPTH {
+        // We reached init, there is no browser above us
+        if pid <= 1 {
+            break;
+        }
+
+        let exe = fs::read_link(format!("/proc/{}/exe", pid))
+            .map_err(GetParentProcError::LinuxResolveParentExeFailed)?;
+        let exe_str = exe.to_string_lossy();
+        let (path, deleted) = match exe_str.strip_suffix(DELETED_EXE_SUFFIX) {
+            Some(path) => (PathBuf::from(path), true),
+            None => (exe.clone(), false)
+        };
+
+        // Content processes are children of the main process, keep going
+        let content_proc = fs::read(format!("/proc/{}/cmdline", pid))
+            .map(|cmdline| cmdline.split(|b| *b == 0).any(|arg| arg == b"-contentproc"))
+            .unwrap_or(false);
+
+        if is_browser_binary(&path) && !content_proc {
+            if deleted {
+                log::warn!("Browser binary was deleted after the browser started (updated?): {:?}", path);
+            }
+            return Ok(ParentProc { path, deleted });
+        }
+
+        log::trace!("Process {} ({:?}) is not the browser, checking its parent.", pid, path);
+        pid = read_linux_ppid(&pid.to_string())?;
+    }
+    Err(GetParentProcError::LinuxNoBrowserAncestor)
+}
+
+// Read the parent PID from /proc/<pid>/stat
+#[cfg(target_os = "linux")]
+fn read_linux_ppid(pid: &str) -> Result<u32, GetParentProcError> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid))
+        .map_err(GetParentProcError::LinuxOpenCurProcFailed)?;
+    // Looks like: <pid> (<comm>) <state> <ppid> ..., the command name may contain spaces and parentheses
+    let ppid_str = stat.rsplit_once(')')
+        .and_then(|(_, fields)| fields.split_whitespace().nth(1))
+        .ok_or(GetParentProcError::LinuxCouldNotFindPPid)?;
+    ppid_str.parse()
+        .map_err(|_| GetParentProcError::LinuxFailedToParsePidString(ppid_str.to_owned()))
+}
+
+pub fn get_parent_proc() -> Result<&'static ParentProc, &'static GetParentProcError> {
     PARENT_PROC.as_ref()
-}
\ No newline at end of file
+}
+
+pub fn get_parent_proc_path() -> Result<&'static PathBuf, &'static GetParentProcError> {
+    get_parent_proc().map(|p| &p.path)
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::{Path, PathBuf};
+    use crate::desktop_entries::LaunchTemplate;
+    use super::{FlatpakInstallation, LaunchBackend};
+
+    // Records the name it was started as and its arguments, one per line
+    const STUB_SCRIPT: &str = "#!/bin/sh\necho \"${0##*/}\" > \"$STUB_ARGV\"\nprintf '%s\\n' \"$@\" >> \"$STUB_ARGV\"\n";
+
+    fn stub_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("fps-test-process-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        for stub in &["flatpak", "flatpak-spawn", "firefox"] {
+            let path = dir.join(stub);
+            fs::write(&path, STUB_SCRIPT).unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        dir
+    }
+
+    // Run the command with only the stubs on the PATH and return the argv the stub received
+    fn run_stubbed(backend: &LaunchBackend, in_flatpak: bool, dir: &Path) -> Vec<String> {
+        let argv_path = dir.join("argv");
+        let status = backend.build_command_from(vec!["-P".to_owned(), "test".to_owned()], in_flatpak)
+            .env("PATH", dir)
+            .env("STUB_ARGV", &argv_path)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let argv = fs::read_to_string(&argv_path).unwrap();
+        fs::remove_dir_all(dir).unwrap();
+        argv.lines().map(str::to_owned).collect()
+    }
+
+    fn flatpak_backend() -> LaunchBackend {
+        LaunchBackend::Flatpak {
+            app_id: "org.mozilla.firefox".to_owned(),
+            branch: Some("stable".to_owned()),
+            arch: Some("x86_64".to_owned()),
+            installation: Some(FlatpakInstallation::User)
+        }
+    }
+
+    #[test]
+    fn direct_runs_binary() {
+        let dir = stub_dir("direct");
+        let backend = LaunchBackend::Direct(dir.join("firefox"));
+        assert_eq!(run_stubbed(&backend, false, &dir), ["firefox", "-P", "test"]);
+    }
+
+    #[test]
+    fn direct_escapes_sandbox() {
+        let dir = stub_dir("direct-sandbox");
+        let binary = dir.join("firefox");
+        let backend = LaunchBackend::Direct(binary.clone());
+        assert_eq!(run_stubbed(&backend, true, &dir),
+                   ["flatpak-spawn".to_owned(), "--host".to_owned(), binary.to_string_lossy().to_string(), "-P".to_owned(), "test".to_owned()]);
+    }
+
+    #[test]
+    fn direct_inside_sandbox_is_not_escaped() {
+        let command = LaunchBackend::Direct(PathBuf::from("/app/lib/firefox/firefox"))
+            .build_command_from(vec!["-P".to_owned(), "test".to_owned()], true);
+        assert_eq!(command.get_program(), "/app/lib/firefox/firefox");
+        assert_eq!(command.get_args().collect::<Vec<_>>(), ["-P", "test"]);
+    }
+
+    #[test]
+    fn flatpak_runs_app() {
+        let dir = stub_dir("flatpak");
+        assert_eq!(run_stubbed(&flatpak_backend(), false, &dir),
+                   ["flatpak", "run", "--branch=stable", "--arch=x86_64", "--user", "org.mozilla.firefox", "-P", "test"]);
+    }
+
+    #[test]
+    fn flatpak_without_details() {
+        let dir = stub_dir("flatpak-minimal");
+        let backend = LaunchBackend::Flatpak {
+            app_id: "org.mozilla.firefox".to_owned(),
+            branch: None,
+            arch: None,
+            installation: Some(FlatpakInstallation::System)
+        };
+        assert_eq!(run_stubbed(&backend, false, &dir),
+                   ["flatpak", "run", "--system", "org.mozilla.firefox", "-P", "test"]);
+    }
+
+    #[test]
+    fn flatpak_escapes_sandbox() {
+        let dir = stub_dir("flatpak-sandbox");
+        assert_eq!(run_stubbed(&flatpak_backend(), true, &dir),
+                   ["flatpak-spawn", "--host", "flatpak", "run", "--branch=stable", "--arch=x86_64", "--user", "org.mozilla.firefox", "-P", "test"]);
+    }
+
+    #[test]
+    fn snap_runs_wrapper() {
+        let command = LaunchBackend::Snap { name: "firefox".to_owned() }
+            .build_command_from(vec!["-P".to_owned(), "test".to_owned()], false);
+        assert_eq!(command.get_program(), "/snap/bin/firefox");
+        assert_eq!(command.get_args().collect::<Vec<_>>(), ["-P", "test"]);
+    }
+
+    #[test]
+    fn snap_escapes_sandbox() {
+        let dir = stub_dir("snap-sandbox");
+        let backend = LaunchBackend::Snap { name: "firefox".to_owned() };
+        assert_eq!(run_stubbed(&backend, true, &dir),
+                   ["flatpak-spawn", "--host", "/snap/bin/firefox", "-P", "test"]);
+    }
+
+    #[test]
+    fn template_inserts_args() {
+        let dir = stub_dir("template");
+        let template = LaunchTemplate::parse_exec("flatpak run --branch=stable --file-forwarding org.mozilla.firefox @@u %u @@").unwrap();
+        let backend = LaunchBackend::Template(template);
+        assert_eq!(run_stubbed(&backend, false, &dir),
+                   ["flatpak", "run", "--branch=stable", "org.mozilla.firefox", "-P", "test"]);
+    }
+
+    #[test]
+    fn template_escapes_sandbox() {
+        let dir = stub_dir("template-sandbox");
+        let template = LaunchTemplate::parse_exec("firefox --new-instance %u").unwrap();
+        let backend = LaunchBackend::Template(template);
+        assert_eq!(run_stubbed(&backend, true, &dir),
+                   ["flatpak-spawn", "--host", "firefox", "--new-instance", "-P", "test"]);
+    }
+}
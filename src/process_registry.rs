@@ -0,0 +1,339 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use cfg_if::cfg_if;
+use crossbeam_channel::{bounded as bounded_channel, unbounded as unbounded_channel, Receiver, Sender};
+use crate::config::Config;
+use crate::ipc::probe_instance;
+use crate::native_resp::{NativeResponseEvent, write_native_event};
+use crate::process::SpawnedBrowser;
+use crate::profiles::{read_profiles, ProfilesIniState};
+use crate::state::AppContext;
+
+cfg_if! {
+    if #[cfg(target_os = "linux")] {
+        use nix::sys::signal::kill;
+        use nix::unistd::Pid;
+    }
+}
+
+// How often the running state of all profiles is re-checked
+const MONITOR_INTERVAL: Duration = Duration::from_secs(3);
+// How often a launching browser is checked for readiness
+const READINESS_POLL_INTERVAL: Duration = Duration::from_millis(200);
+// Only the end of the browser output is interesting when it fails to start
+const MAX_LAUNCH_LOG_LEN: usize = 4096;
+
+// === PROCESS REGISTRY ===
+
+// A browser process that was launched by this connector
+#[derive(Clone, Debug)]
+pub struct LaunchedBrowser {
+    pub pid: u32,
+    pub started_at: SystemTime,
+    pub binary: PathBuf
+}
+
+#[derive(Clone, Debug)]
+pub struct RunningProfile {
+    pub profile_id: String,
+    pub pid: Option<u32>,
+    pub started_at: Option<SystemTime>,
+    pub binary: Option<PathBuf>,
+    pub launched_by_connector: bool
+}
+
+#[derive(Clone, Debug)]
+pub struct ProcessRegistry {
+    launched: Arc<RwLock<HashMap<String, LaunchedBrowser>>>,
+    changed_sender: Sender<()>,
+    changed_receiver: Receiver<()>
+}
+
+impl ProcessRegistry {
+    pub fn new() -> Self {
+        let (changed_sender, changed_receiver) = unbounded_channel();
+        ProcessRegistry {
+            launched: Arc::new(RwLock::new(HashMap::new())),
+            changed_sender,
+            changed_receiver
+        }
+    }
+
+    /// Record a browser we just started for a profile and reap it once it exits.
+    pub fn register(&self, profile_id: &str, spawned: SpawnedBrowser) -> PendingLaunch {
+        let SpawnedBrowser { binary, mut child, stderr_log } = spawned;
+        let pid = child.id();
+        log::trace!("Registering browser process {} ({:?}) for profile: {}", pid, binary, profile_id);
+
+        self.launched.write().unwrap().insert(profile_id.to_owned(), LaunchedBrowser {
+            pid,
+            started_at: SystemTime::now(),
+            binary
+        });
+        self.notify_changed();
+
+        let (exit_sender, exit_receiver) = bounded_channel(1);
+        let registry = self.clone();
+        let pending = PendingLaunch {
+            profile_id: profile_id.to_owned(),
+            pid,
+            exit_receiver,
+            stderr_log
+        };
+        let profile_id = profile_id.to_owned();
+        thread::spawn(move || {
+            let status = match child.wait() {
+                Ok(status) => {
+                    log::trace!("Browser process {} for profile {} exited: {}", pid, profile_id, status);
+                    Some(status)
+                },
+                Err(e) => {
+                    log::warn!("Failed to wait for browser process {}: {:?}", pid, e);
+                    None
+                }
+            };
+            // Nobody may be waiting for the launch to finish anymore
+            let _ = exit_sender.send(status);
+
+            {
+                let mut launched = registry.launched.write().unwrap();
+                // The profile may have been launched again in the meantime
+                if launched.get(&profile_id).map(|l| l.pid) == Some(pid) {
+                    launched.remove(&profile_id);
+                }
+            }
+            registry.notify_changed();
+        });
+
+        pending
+    }
+
+    fn notify_changed(&self) {
+        // Receiver lives as long as we do
+        let _ = self.changed_sender.send(());
+    }
+
+    /// List every profile that currently has a browser running, either because we launched it or
+    /// because the profile lock is held.
+    pub fn list_running(&self, config: &Config, profiles: &ProfilesIniState) -> Vec<RunningProfile> {
+        let launched = self.launched.read().unwrap();
+        profiles.profile_entries.iter()
+            .filter_map(|profile| {
+                let lock = read_profile_lock(&profile.full_path(config));
+                let launched = launched.get(&profile.id);
+                if lock.is_none() && launched.is_none() {
+                    return None;
+                }
+
+                Some(RunningProfile {
+                    profile_id: profile.id.clone(),
+                    // The lock holds the PID of the main browser process, our PID may belong to a launcher
+                    pid: lock.as_ref()
+                        .and_then(|l| l.pid)
+                        .or_else(|| launched.map(|l| l.pid)),
+                    started_at: launched.map(|l| l.started_at)
+                        .or_else(|| lock.as_ref().and_then(|l| l.since)),
+                    binary: launched.map(|l| l.binary.clone()),
+                    launched_by_connector: launched.is_some()
+                })
+            })
+            .collect()
+    }
+}
+
+// Why a launched browser is considered to have started
+#[derive(Debug)]
+pub enum LaunchReadiness {
+    // The browser exited successfully right away, it passed the request on to an already running instance
+    HandedOff,
+    ProfileLocked,
+    ConnectorReachable
+}
+
+#[derive(Debug)]
+pub enum LaunchReadinessError {
+    ExitedEarly { code: Option<i32>, stderr: String },
+    // Another browser still held the profile lock when we gave up waiting
+    ProfileInUse { pid: Option<u32> },
+    TimedOut
+}
+
+// A browser that was just launched but may not have started yet
+pub struct PendingLaunch {
+    profile_id: String,
+    pid: u32,
+    exit_receiver: Receiver<Option<ExitStatus>>,
+    stderr_log: Option<PathBuf>
+}
+
+impl PendingLaunch {
+    /// Block until the browser has taken the profile lock, its connector can be reached or the
+    /// browser exits. `lock_before` is the profile lock as it was before the browser was launched,
+    /// a lock still held by another browser doesn't mean ours started.
+    pub fn wait_until_ready(&self, profile_path: &Path, lock_before: &LockSnapshot, timeout: Duration) -> Result<LaunchReadiness, LaunchReadinessError> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Ok(status) = self.exit_receiver.try_recv() {
+                return match status {
+                    Some(status) if status.success() => Ok(LaunchReadiness::HandedOff),
+                    status => Err(LaunchReadinessError::ExitedEarly {
+                        code: status.and_then(|s| s.code()),
+                        stderr: self.read_stderr_log()
+                    })
+                };
+            }
+            // The browser that held the lock before may be hung, ours then exits complaining that the
+            // profile is already in use or waits for the user to dismiss a dialog saying so
+            let foreign_lock = match read_profile_lock(profile_path) {
+                Some(lock) if lock.pid == Some(self.pid) || lock_before.0.as_ref() != Some(&lock) => {
+                    return Ok(LaunchReadiness::ProfileLocked);
+                }
+                lock => lock
+            };
+            if probe_instance(&self.profile_id) {
+                return Ok(LaunchReadiness::ConnectorReachable);
+            }
+            if Instant::now() >= deadline {
+                return Err(match foreign_lock {
+                    Some(lock) => LaunchReadinessError::ProfileInUse { pid: lock.pid },
+                    None => LaunchReadinessError::TimedOut
+                });
+            }
+            thread::sleep(READINESS_POLL_INTERVAL);
+        }
+    }
+
+    fn read_stderr_log(&self) -> String {
+        let log_path = match &self.stderr_log {
+            Some(p) => p,
+            None => return String::new()
+        };
+        match fs::read(log_path) {
+            Ok(output) => {
+                let start = output.len().saturating_sub(MAX_LAUNCH_LOG_LEN);
+                String::from_utf8_lossy(&output[start..]).trim().to_owned()
+            }
+            Err(e) => {
+                log::warn!("Failed to read browser launch log {:?}: {:?}", log_path, e);
+                String::new()
+            }
+        }
+    }
+}
+
+// Watch all profiles and emit an event whenever one starts or stops running
+pub fn run_process_monitor(context: &AppContext) {
+    let mut last_running: Option<HashMap<String, Option<u32>>> = None;
+
+    loop {
+        match read_profiles(&context.state().config, &context.state().config_dir) {
+            Ok(profiles) => {
+                let running: HashMap<String, Option<u32>> = context.processes
+                    .list_running(&context.state().config, &profiles)
+                    .into_iter()
+                    .map(|p| (p.profile_id, p.pid))
+                    .collect();
+
+                // Do not flood the extension with events on startup, it can ask for the full list instead
+                if let Some(last_running) = &last_running {
+                    for (profile_id, pid) in &running {
+                        if last_running.get(profile_id) != Some(pid) {
+                            write_native_event(NativeResponseEvent::ProfileRunningStateChanged {
+                                profile_id: profile_id.clone(),
+                                running: true,
+                                pid: *pid
+                            });
+                        }
+                    }
+                    for profile_id in last_running.keys().filter(|id| !running.contains_key(*id)) {
+                        write_native_event(NativeResponseEvent::ProfileRunningStateChanged {
+                            profile_id: profile_id.clone(),
+                            running: false,
+                            pid: None
+                        });
+                    }
+                }
+
+                last_running = Some(running);
+            }
+            Err(e) => log::warn!("Failed to read profiles while monitoring processes: {:?}", e)
+        }
+
+        // Wake up early if a process we launched started or exited
+        let _ = context.processes.changed_receiver.recv_timeout(MONITOR_INTERVAL);
+        while context.processes.changed_receiver.try_recv().is_ok() {}
+    }
+}
+
+// Check whether a browser currently has the profile at the specified path open
+pub fn is_profile_running(profile_path: &Path) -> bool {
+    read_profile_lock(profile_path).is_some()
+}
+
+#[derive(PartialEq, Debug)]
+struct ProfileLock {
+    pid: Option<u32>,
+    since: Option<SystemTime>
+}
+
+// The profile lock at some point in time
+pub struct LockSnapshot(Option<ProfileLock>);
+
+/// Remember who holds the lock of the profile at the specified path, taken before launching a
+/// browser for it.
+pub fn snapshot_profile_lock(profile_path: &Path) -> LockSnapshot {
+    LockSnapshot(read_profile_lock(profile_path))
+}
+
+// Check whether a browser holds the lock of the profile at the specified path
+fn read_profile_lock(profile_path: &Path) -> Option<ProfileLock> {
+    cfg_if! {
+        if #[cfg(target_os = "linux")] {
+            // Firefox creates a symlink pointing to "<ip>:+<pid>"
+            let lock_path = profile_path.join("lock");
+            let target = fs::read_link(&lock_path).ok()?;
+            let pid = target.to_str()?
+                .rsplit_once(":+")?
+                .1
+                .parse::<u32>()
+                .ok()?;
+            // Stale locks are left behind when the browser crashes
+            if kill(Pid::from_raw(pid as i32), None).is_err() {
+                return None;
+            }
+            let since = fs::symlink_metadata(&lock_path)
+                .and_then(|m| m.modified())
+                .ok();
+            Some(ProfileLock { pid: Some(pid), since })
+        } else {
+            use fs2::FileExt;
+            use std::fs::OpenOptions;
+
+            let lock_path = if cfg!(target_os = "macos") {
+                profile_path.join(".parentlock")
+            } else {
+                profile_path.join("parent.lock")
+            };
+            let lock_file = match OpenOptions::new().read(true).open(&lock_path) {
+                Ok(f) => f,
+                // Windows refuses to open the lock while the browser holds it
+                Err(_) => return fs::metadata(&lock_path)
+                    .ok()
+                    .map(|m| ProfileLock { pid: None, since: m.modified().ok() })
+            };
+            if lock_file.try_lock_shared().is_ok() {
+                let _ = lock_file.unlock();
+                return None;
+            }
+            let since = lock_file.metadata()
+                .and_then(|m| m.modified())
+                .ok();
+            Some(ProfileLock { pid: None, since })
+        }
+    }
+}
@@ -0,0 +1,228 @@
+use std::path::{Path, PathBuf};
+use serde::Serialize;
+use crate::process::{get_parent_proc, LaunchBackend};
+
+// === PROFILE ROOT RESOLUTION ===
+
+// Where the profile root (the directory containing profiles.ini) came from
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ProfileRootSource {
+    // FPS_PROFILE_ROOT or --profile-root
+    Override,
+    // browser_profile_dir in the connector config
+    Configured,
+    // Derived from the browser that launched us
+    ParentBrowser,
+    // The first profile root of a known browser that exists
+    Fallback
+}
+
+#[derive(Clone, Debug)]
+pub struct ProfileRootResolution {
+    pub root: PathBuf,
+    pub source: ProfileRootSource,
+    // Human readable description of how the root was found, for troubleshooting
+    pub steps: Vec<String>
+}
+
+// What we know about the browser that launched us
+struct BrowserIdentity {
+    binary: PathBuf,
+    backend: LaunchBackend,
+    vendor: Option<String>,
+    name: Option<String>,
+    // Overrides the vendor/name derived profile path
+    profile: Option<String>
+}
+
+fn log_step(steps: &mut Vec<String>, step: String) {
+    log::info!("Profile root resolution: {}", step);
+    steps.push(step);
+}
+
+/// Find the profile root of the browser that launched us. Returns None if we can't tell which
+/// browser that is.
+pub fn resolve_profile_root_from_parent(steps: &mut Vec<String>) -> Option<PathBuf> {
+    // Paths are virtualized inside MSIX packages, the known browser dirs already account for that
+    cfg_if::cfg_if! {
+        if #[cfg(target_os = "windows")] {
+            if let Ok(msix_package) = crate::config::get_msix_package() {
+                log_step(steps, format!("Browser is the MSIX package {}", msix_package));
+                return None;
+            }
+        }
+    }
+
+    let parent_proc = match get_parent_proc() {
+        Ok(p) => p,
+        Err(e) => {
+            log_step(steps, format!("Could not determine the browser that launched the connector: {:?}", e));
+            return None;
+        }
+    };
+    log_step(steps, format!("Browser binary: {:?}{}", parent_proc.path, if parent_proc.deleted { " (deleted)" } else { "" }));
+
+    let identity = read_browser_identity(&parent_proc.path, steps);
+
+    // Profiles are stored relative to the home dir the browser sees
+    let homes = browser_home_dirs(&identity, steps);
+
+    let app_ini_candidates = app_ini_profile_dirs(&identity);
+    let binary_candidates = binary_name_profile_dirs(&identity);
+    for home in &homes {
+        for candidate in app_ini_candidates.iter().chain(binary_candidates.iter()) {
+            let dir = home.join(candidate);
+            if dir.join("profiles.ini").exists() {
+                log_step(steps, format!("Found profiles.ini in {:?}", dir));
+                return Some(dir);
+            }
+            log_step(steps, format!("No profiles.ini in {:?}", dir));
+        }
+    }
+
+    // We know exactly which dir the browser uses, it just hasn't created any profiles yet
+    if let (Some(home), Some(candidate)) = (homes.first(), app_ini_candidates.first()) {
+        let dir = home.join(candidate);
+        log_step(steps, format!("Using {:?} derived from application.ini even though it has no profiles.ini", dir));
+        return Some(dir);
+    }
+
+    None
+}
+
+fn read_browser_identity(binary: &Path, steps: &mut Vec<String>) -> BrowserIdentity {
+    let backend = LaunchBackend::for_binary(binary);
+    log_step(steps, format!("Browser launch backend: {:?}", backend));
+
+    // On macOS application.ini is in Contents/Resources, the binary is in Contents/MacOS
+    let app_ini_path = binary.parent()
+        .map(|dir| if dir.ends_with("Contents/MacOS") {
+            dir.with_file_name("Resources").join("application.ini")
+        } else {
+            dir.join("application.ini")
+        });
+
+    let app_ini = app_ini_path.as_ref().and_then(|p| match ini::Ini::load_from_file(p) {
+        Ok(ini) => Some(ini),
+        Err(e) => {
+            log_step(steps, format!("Could not read {:?}: {:?}", p, e));
+            None
+        }
+    });
+    let app_value = |key: &str| app_ini.as_ref()
+        .and_then(|ini| ini.get_from(Some("App"), key))
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+        .map(str::to_owned);
+
+    let identity = BrowserIdentity {
+        binary: binary.to_path_buf(),
+        backend,
+        vendor: app_value("Vendor"),
+        name: app_value("Name"),
+        profile: app_value("Profile")
+    };
+    if app_ini.is_some() {
+        log_step(steps, format!("application.ini: vendor {:?}, name {:?}, profile {:?}", identity.vendor, identity.name, identity.profile));
+    }
+    identity
+}
+
+fn browser_home_dirs(identity: &BrowserIdentity, steps: &mut Vec<String>) -> Vec<PathBuf> {
+    let base_dirs = match directories::BaseDirs::new() {
+        Some(d) => d,
+        None => return Vec::new()
+    };
+
+    cfg_if::cfg_if! {
+        if #[cfg(target_os = "linux")] {
+            let home = base_dirs.home_dir().to_path_buf();
+            let mut homes = Vec::new();
+            match &identity.backend {
+                LaunchBackend::Flatpak { app_id, .. } => {
+                    log_step(steps, format!("Browser is the Flatpak {}", app_id));
+                    homes.push(home.join(".var").join("app").join(app_id));
+                }
+                LaunchBackend::Snap { name } => {
+                    log_step(steps, format!("Browser is the snap {}", name));
+                    homes.push(home.join("snap").join(name).join("common"));
+                    homes.push(home.join("snap").join(name).join("current"));
+                }
+                _ => {}
+            }
+            homes.push(home);
+            // Newer versions store profiles in the XDG config dir
+            homes.push(base_dirs.config_dir().to_path_buf());
+            homes
+        } else if #[cfg(target_os = "macos")] {
+            let _ = steps;
+            vec![base_dirs.data_dir().to_path_buf()]
+        } else {
+            let _ = steps;
+            // Roaming app data
+            vec![base_dirs.config_dir().to_path_buf()]
+        }
+    }
+}
+
+// The profile root relative to the home dir, the same way the browser derives it from application.ini
+fn app_ini_profile_dirs(identity: &BrowserIdentity) -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    if let Some(profile) = &identity.profile {
+        dirs.push(PathBuf::from(platform_dir_name(profile, true)));
+        dirs.push(PathBuf::from(profile));
+    }
+    if let Some(name) = &identity.name {
+        let mut vendor_dirs = Vec::new();
+        if let Some(vendor) = &identity.vendor {
+            vendor_dirs.push(Path::new(&platform_dir_name(vendor, true)).join(platform_dir_name(name, false)));
+            // The XDG layout does not hide the vendor dir
+            vendor_dirs.push(Path::new(&platform_dir_name(vendor, false)).join(platform_dir_name(name, false)));
+        }
+        let name_dir = PathBuf::from(platform_dir_name(name, true));
+        // macOS browsers skip the vendor dir
+        if cfg!(target_os = "macos") {
+            dirs.push(name_dir);
+            dirs.extend(vendor_dirs);
+        } else {
+            dirs.extend(vendor_dirs);
+            dirs.push(name_dir);
+        }
+    }
+    dirs.dedup();
+    dirs
+}
+
+// Without application.ini all we have is the name of the binary (e.g. librewolf -> ~/.librewolf)
+fn binary_name_profile_dirs(identity: &BrowserIdentity) -> Vec<PathBuf> {
+    let name = match identity.binary.file_stem().and_then(|n| n.to_str()) {
+        Some(n) => n.trim_end_matches("-bin"),
+        None => return Vec::new()
+    };
+    vec![
+        Path::new(&platform_dir_name("mozilla", true)).join(platform_dir_name(name, false)),
+        PathBuf::from(platform_dir_name(name, true))
+    ]
+}
+
+// Linux uses lowercase dot-dirs, the other platforms use the name as is
+fn platform_dir_name(name: &str, top_level: bool) -> String {
+    cfg_if::cfg_if! {
+        if #[cfg(target_os = "linux")] {
+            let name = name.to_lowercase();
+            if top_level && !name.starts_with('.') {
+                format!(".{}", name)
+            } else {
+                name
+            }
+        } else {
+            let _ = top_level;
+            let mut chars = name.chars();
+            match chars.next() {
+                Some(c) => c.to_uppercase().chain(chars).collect(),
+                None => String::new()
+            }
+        }
+    }
+}
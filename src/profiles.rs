@@ -1,12 +1,14 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use serde_json::Value;
 use serde::{Deserialize, Serialize};
-use crate::config::Config;
+use crate::config::{Config, ProfileRootConfig};
 use std::path::{PathBuf, Path};
 use ini::{EscapePolicy, Ini, ParseOption};
 use std::io;
-use std::fs::OpenOptions;
-use crate::storage::{avatar_data_path, options_data_path, order_data_path};
+use std::fs;
+use crate::stores::{Migration, VersionedStore, INITIAL_MIGRATIONS};
+use crate::metadata::{read_metadata, update_metadata, Metadata};
+use crate::profiles_order::OrderData;
 use ring::digest::{Context, SHA256};
 use data_encoding::HEXUPPER;
 
@@ -18,13 +20,17 @@ pub struct ProfileEntry {
     pub path: String,
     pub default: bool,
     pub avatar: Option<String>,
-    pub options: HashMap<String, Value>
+    pub options: HashMap<String, Value>,
+    // The extra profile root this profile belongs to, None for the primary root
+    pub root_id: Option<String>,
+    // Name of the browser of the extra profile root
+    pub browser: Option<String>
 }
 
 impl ProfileEntry {
     pub fn full_path(&self, config: &Config) -> PathBuf {
         if self.is_relative {
-            let mut result = config.browser_profile_dir();
+            let mut result = config.profile_root_dir(self.root_id.as_deref());
             result.push(&self.path);
             result
         } else {
@@ -34,28 +40,57 @@ impl ProfileEntry {
 }
 
 pub struct ProfilesIniState {
-    backing_ini: Ini,
-    pub profile_entries: Vec<ProfileEntry>
+    // Non-profile keys of the profiles.ini of every root that was read
+    backing_inis: Vec<(Option<String>, Ini)>,
+    pub profile_entries: Vec<ProfileEntry>,
+    // IDs of the profiles that were read and the metadata they were read with, so that only our own
+    // changes are written back
+    read_profile_ids: HashSet<String>,
+    read_metadata: Metadata
+}
+
+impl ProfilesIniState {
+    /// Whether the profile was read from disk. Profiles of roots that could not be read and profiles
+    /// created by other instances since are missing from `profile_entries` even though they exist.
+    pub fn was_read(&self, profile_id: &str) -> bool {
+        self.read_profile_ids.contains(profile_id)
+    }
+
+    #[cfg(test)]
+    pub fn from_entries(profile_entries: Vec<ProfileEntry>) -> ProfilesIniState {
+        ProfilesIniState {
+            backing_inis: Vec::new(),
+            read_profile_ids: profile_entries.iter().map(|p| p.id.clone()).collect(),
+            profile_entries,
+            read_metadata: Metadata::default()
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Default)]
-struct AvatarData {
-    avatars: HashMap<String, String>
+pub struct AvatarData {
+    pub avatars: HashMap<String, String>
+}
+
+impl VersionedStore for AvatarData {
+    const DESCRIPTION: &'static str = "avatar data";
+    const MIGRATIONS: &'static [Migration] = INITIAL_MIGRATIONS;
 }
 
 #[derive(Serialize, Deserialize, Debug, Default)]
-struct OptionsData {
-    options: HashMap<String, HashMap<String, Value>>
+pub struct OptionsData {
+    pub options: HashMap<String, HashMap<String, Value>>
+}
+
+impl VersionedStore for OptionsData {
+    const DESCRIPTION: &'static str = "options data";
+    const MIGRATIONS: &'static [Migration] = INITIAL_MIGRATIONS;
 }
 
 #[derive(Debug)]
 pub enum ReadProfilesError {
     BadIniFormat,
     IniError(ini::Error),
-    AvatarStoreError(io::Error),
-    BadAvatarStoreFormat(serde_json::Error),
-    OptionsStoreError(io::Error),
-    BadOptionsStoreFormat(serde_json::Error),
 }
 
 const MOZ_INI_PARSE_OPTION: ParseOption = ParseOption {
@@ -69,40 +104,54 @@ const MOZ_INI_PARSE_OPTION: ParseOption = ParseOption {
 const MOZ_INI_ESCAPE_POLICY: EscapePolicy = EscapePolicy::Nothing;
 
 pub fn read_profiles(config: &Config, config_dir: &Path) -> Result<ProfilesIniState, ReadProfilesError> {
-    let profiles_conf = Ini::load_from_file_opt(config.profiles_ini_path(), MOZ_INI_PARSE_OPTION)
-        .map_err(ReadProfilesError::IniError)?;
-
-    let avatar_data: AvatarData = OpenOptions::new()
-        .read(true)
-        .open(avatar_data_path(config_dir))
-        .map_err(ReadProfilesError::AvatarStoreError)
-        .and_then(|f| serde_json::from_reader(f)
-            .map_err(ReadProfilesError::BadAvatarStoreFormat))
-        .unwrap_or_else(|e| {
-            log::warn!("Failed to read avatar data: {:?}, falling back to defaults", e);
-            AvatarData::default()
-        });
-
-    let options_data: OptionsData = OpenOptions::new()
-        .read(true)
-        .open(options_data_path(config_dir))
-        .map_err(ReadProfilesError::OptionsStoreError)
-        .and_then(|f| serde_json::from_reader(f)
-            .map_err(ReadProfilesError::BadOptionsStoreFormat))
-        .unwrap_or_else(|e| {
-            log::warn!("Failed to read options data: {:?}, falling back to defaults", e);
-            OptionsData::default()
-        });
+    let metadata = read_metadata(config_dir);
 
     let mut state = ProfilesIniState {
-        backing_ini: Ini::new(),
+        backing_inis: Vec::new(),
         profile_entries: Vec::new(),
+        read_profile_ids: HashSet::new(),
+        read_metadata: Metadata::default()
     };
 
+    // The browser that launched us may be one of the extra roots. Its profiles keep their qualified
+    // IDs, so they don't depend on which browser launched us.
+    let primary_dir = fs::canonicalize(config.browser_profile_dir()).ok();
+    let primary_root = config.extra_profile_roots().iter()
+        .find(|root| primary_dir.is_some() && fs::canonicalize(config.profile_root_dir(Some(&root.id))).ok() == primary_dir);
+    if let Some(root) = primary_root {
+        log::trace!("Primary root is the profile root {}", root.id);
+    }
+    read_profile_root(config, primary_root, &metadata, &mut state)?;
+
+    for root in config.extra_profile_roots() {
+        if primary_root.map_or(false, |r| r.id == root.id) {
+            continue;
+        }
+        // Do not lock the user out of all profiles because one browser is missing
+        if let Err(e) = read_profile_root(config, Some(root), &metadata, &mut state) {
+            log::warn!("Failed to read profiles of root {}: {:?}", root.id, e);
+        }
+    }
+
+    state.read_profile_ids = state.profile_entries.iter().map(|p| p.id.clone()).collect();
+    state.read_metadata = metadata;
+    Ok(state)
+}
+
+fn read_profile_root(config: &Config,
+                     root: Option<&ProfileRootConfig>,
+                     metadata: &Metadata,
+                     state: &mut ProfilesIniState) -> Result<(), ReadProfilesError> {
+    let root_id = root.map(|r| r.id.clone());
+    let profiles_conf = Ini::load_from_file_opt(config.profiles_ini_path(root_id.as_deref()), MOZ_INI_PARSE_OPTION)
+        .map_err(ReadProfilesError::IniError)?;
+
+    let mut backing_ini = Ini::new();
+    let mut profile_entries = Vec::new();
     for (sec, prop) in &profiles_conf {
         if sec.is_none() || !sec.unwrap().starts_with("Profile") {
             // Save non-profile keys in new INI file
-            let mut section_setter = &mut state.backing_ini.with_section(sec);
+            let mut section_setter = &mut backing_ini.with_section(sec);
             for (key, value) in prop.iter() {
                 section_setter = section_setter.set(key, value);
             }
@@ -129,80 +178,88 @@ pub fn read_profiles(config: &Config, config_dir: &Path) -> Result<ProfilesIniSt
 
             let profile_path = profile_path.unwrap();
             let profile_is_relative = profile_is_relative.unwrap();
-            let profile_id = calc_profile_id(&profile_path, profile_is_relative);
-            let avatar = avatar_data.avatars.get(&profile_id).map(String::clone);
-            let options = options_data.options
+            let profile_id = qualify_profile_id(root_id.as_deref(), &calc_profile_id(&profile_path, profile_is_relative));
+            let avatar = metadata.avatars.get(&profile_id).map(String::clone);
+            let options = metadata.profile_options
                 .get(&profile_id)
                 .map(HashMap::clone)
                 .unwrap_or_else(HashMap::new);
 
-            state.profile_entries.push(ProfileEntry {
+            profile_entries.push(ProfileEntry {
                 id: profile_id,
                 name: profile_name.unwrap(),
                 is_relative: profile_is_relative,
                 path: profile_path,
                 default: profile_default,
                 avatar,
-                options
+                options,
+                root_id: root_id.clone(),
+                browser: root.map(|r| r.name.clone().unwrap_or_else(|| r.id.clone()))
             });
         }
     }
 
-    Ok(state)
+    state.backing_inis.push((root_id, backing_ini));
+    state.profile_entries.extend(profile_entries);
+    Ok(())
 }
 
 #[derive(Debug)]
 pub enum WriteProfilesError {
     WriteIniError(io::Error),
-    OpenAvatarFileError(io::Error),
-    WriteAvatarFileError(serde_json::Error),
-    OpenOptionsFileError(io::Error),
-    WriteOptionsFileError(serde_json::Error),
+    MetadataStoreError(eyre::Report),
     OpenOrderFileError(io::Error),
     WriteOrderFileError(serde_json::Error),
 }
 pub fn write_profiles(config: &Config, config_dir: &Path, state: &ProfilesIniState) -> Result<(), WriteProfilesError> {
-    // Build avatar data
-    let mut avatar_data = AvatarData {
-        avatars: HashMap::new()
-    };
-    let mut options_data = OptionsData {
-        options: HashMap::new()
-    };
-    for profile in &state.profile_entries {
-        if let Some(avatar) = &profile.avatar {
-            avatar_data.avatars.insert(profile.id.clone(), avatar.clone());
+    // Avatars, options and order of the profiles are changed together. Other instances may have
+    // changed the metadata since we read it, so only what we changed is applied.
+    update_metadata(config_dir, |metadata| {
+        let present: HashSet<&str> = state.profile_entries.iter().map(|p| p.id.as_str()).collect();
+        for profile_id in &state.read_profile_ids {
+            if !present.contains(profile_id.as_str()) {
+                metadata.avatars.remove(profile_id);
+                metadata.profile_options.remove(profile_id);
+            }
         }
-        options_data.options.insert(profile.id.clone(), profile.options.clone());
-    }
 
-    // Write avatar data
-    let avatar_file = OpenOptions::new()
-        .create(true)
-        .truncate(true)
-        .write(true)
-        .open(avatar_data_path(config_dir))
-        .map_err(WriteProfilesError::OpenAvatarFileError)?;
+        for profile in &state.profile_entries {
+            let was_read = state.was_read(&profile.id);
+            if !was_read || profile.avatar.as_ref() != state.read_metadata.avatars.get(&profile.id) {
+                match &profile.avatar {
+                    Some(avatar) => { metadata.avatars.insert(profile.id.clone(), avatar.clone()); }
+                    None => { metadata.avatars.remove(&profile.id); }
+                }
+            }
+            let read_options = state.read_metadata.profile_options.get(&profile.id);
+            if !was_read || read_options.map_or(!profile.options.is_empty(), |o| *o != profile.options) {
+                metadata.profile_options.insert(profile.id.clone(), profile.options.clone());
+            }
+        }
 
-    serde_json::to_writer(avatar_file, &avatar_data)
-        .map_err(WriteProfilesError::WriteAvatarFileError)?;
+        let mut order_data = OrderData { order: metadata.order.clone() };
+        order_data.recalculate(state);
+        metadata.order = order_data.order;
+        Ok(())
+    }).map_err(WriteProfilesError::MetadataStoreError)?;
 
-    // Write options data
-    let options_file = OpenOptions::new()
-        .create(true)
-        .truncate(true)
-        .write(true)
-        .open(options_data_path(config_dir))
-        .map_err(WriteProfilesError::OpenOptionsFileError)?;
+    // Write profile data of every root
+    for (root_id, backing_ini) in &state.backing_inis {
+        let profiles = state.profile_entries.iter().filter(|p| &p.root_id == root_id);
+        write_profiles_ini(config, root_id.as_deref(), backing_ini, profiles)?;
+    }
 
-    serde_json::to_writer(options_file, &options_data)
-        .map_err(WriteProfilesError::WriteOptionsFileError)?;
+    Ok(())
+}
 
-    // Write profile data
-    let mut new_ini = state.backing_ini.clone();
+fn write_profiles_ini<'a>(config: &Config,
+                          root_id: Option<&str>,
+                          backing_ini: &Ini,
+                          profiles: impl Iterator<Item = &'a ProfileEntry>) -> Result<(), WriteProfilesError> {
+    let mut new_ini = backing_ini.clone();
 
     let mut default_profile_path = None::<&str>;
-    for (i, profile) in state.profile_entries.iter().enumerate() {
+    for (i, profile) in profiles.enumerate() {
         let mut section = &mut new_ini.with_section(Some("Profile".to_owned() + &i.to_string()));
         section = section.set("Name", profile.name.as_str())
             .set("IsRelative", if profile.is_relative { "1" } else { "0" })
@@ -224,13 +281,13 @@ pub fn write_profiles(config: &Config, config_dir: &Path, state: &ProfilesIniSta
         }
     }
 
-    if let Err(e) = new_ini.write_to_file_policy(config.profiles_ini_path(), MOZ_INI_ESCAPE_POLICY) {
+    if let Err(e) = new_ini.write_to_file_policy(config.profiles_ini_path(root_id), MOZ_INI_ESCAPE_POLICY) {
         return Err(WriteProfilesError::WriteIniError(e))
     }
 
     // Write install INI
     if let Some(default_profile_path) = default_profile_path {
-        let installs_conf = Ini::load_from_file_opt(config.installs_ini_path(), MOZ_INI_PARSE_OPTION);
+        let installs_conf = Ini::load_from_file_opt(config.installs_ini_path(root_id), MOZ_INI_PARSE_OPTION);
         if let Ok(mut installs_conf) = installs_conf {
             for (sec, prop) in &mut installs_conf {
                 if let Some(_) = sec {
@@ -240,7 +297,7 @@ pub fn write_profiles(config: &Config, config_dir: &Path, state: &ProfilesIniSta
                     }
                 }
             }
-            if let Err(e) = installs_conf.write_to_file_policy(config.installs_ini_path(), MOZ_INI_ESCAPE_POLICY) {
+            if let Err(e) = installs_conf.write_to_file_policy(config.installs_ini_path(root_id), MOZ_INI_ESCAPE_POLICY) {
                 log::warn!("Failed to write installs.ini: {:?}", e);
             }
         }
@@ -249,6 +306,14 @@ pub fn write_profiles(config: &Config, config_dir: &Path, state: &ProfilesIniSta
     Ok(())
 }
 
+// Profiles of extra roots are prefixed with the root ID so they can't clash with the primary root
+pub fn qualify_profile_id(root_id: Option<&str>, profile_id: &str) -> String {
+    match root_id {
+        Some(root_id) => format!("{}:{}", root_id, profile_id),
+        None => profile_id.to_owned()
+    }
+}
+
 pub fn calc_profile_id(path: &str, is_relative: bool) -> String {
     let mut context = Context::new(&SHA256);
     context.update(&[is_relative as u8]);
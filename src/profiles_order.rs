@@ -1,22 +1,26 @@
-use std::collections::HashMap;
-use std::fs::OpenOptions;
+use std::collections::{HashMap, HashSet};
 use std::path::Path;
-use eyre::Context;
 use serde::{Serialize, Deserialize};
-use crate::ipc::notify_update_profile_order;
+use crate::metadata::{read_metadata, update_metadata};
+use crate::stores::{Migration, VersionedStore, INITIAL_MIGRATIONS};
 use crate::native_resp::{NativeResponseEvent, write_native_event};
 use crate::profiles::ProfilesIniState;
-use crate::state::{AppContext, AppState};
-use crate::storage::order_data_path;
+use crate::state::AppState;
 
 #[derive(Serialize, Deserialize, Debug, Default)]
 pub struct OrderData {
     pub order: Vec<String>
 }
 
+impl VersionedStore for OrderData {
+    const DESCRIPTION: &'static str = "profile order data";
+    const MIGRATIONS: &'static [Migration] = INITIAL_MIGRATIONS;
+}
+
 impl OrderData {
-    /// Re-calculate the `profile_order` array, removing any profiles that are no longer present and
-    /// adding any new profiles.
+    /// Re-calculate the `profile_order` array, removing any profiles that were deleted and adding any
+    /// new profiles. Profiles we did not read, such as those of roots that could not be read, keep
+    /// their place.
     pub fn recalculate(&mut self, profiles: &ProfilesIniState) {
         let mut profile_indicies: HashMap<&str, usize> = HashMap::new();
         for (idx, profile_id) in self.order.iter().enumerate() {
@@ -28,51 +32,34 @@ impl OrderData {
         // This will also preserve creation order since the sort is stable
         let mut new_profile_order: Vec<String> = profiles.profile_entries.iter()
             .map(|p| p.id.clone())
+            .chain(self.order.iter().filter(|id| !profiles.was_read(id)).cloned())
             .collect();
         new_profile_order.sort_by_key(|id| profile_idx(id));
         self.order = new_profile_order;
     }
 
-    /// Performs the following operations in sequence:
-    /// - read
-    /// - recalculate
-    /// - write
-    /// - ipc notify profile order updated
-    /// Will log if the write fails.
-    pub fn try_rewrite(context: &AppContext, profiles: &ProfilesIniState) {
-        let mut order_data = Self::read(&context.state.config_dir);
-        order_data.recalculate(profiles);
-        if let Err(e) = order_data.write(&context.state.config_dir) {
-            log::error!("Failed to update profiles order: {:?}", e);
-        } else {
-            notify_update_profile_order(context, profiles);
-        }
-    }
-
     pub fn read(config_dir: &Path) -> OrderData {
-        OpenOptions::new()
-            .read(true)
-            .open(order_data_path(config_dir))
-            .context("could not open profile order data file")
-            .and_then(|f| serde_json::from_reader(f)
-                .context("profile order data file is incorrectly formatted"))
-            .unwrap_or_else(|e| {
-                log::warn!("Failed to read options data: {:?}, falling back to defaults", e);
-                OrderData::default()
-            })
+        OrderData { order: read_metadata(config_dir).order }
     }
 
+    /// Move the profiles in `order` to the places the stored order has for them, in the given order.
+    /// Profiles that are not part of `order` stay where they are, profiles that the stored order is
+    /// missing are added at the end.
     pub fn write(&self, config_dir: &Path) -> eyre::Result<()> {
-        // Write order data
-        let order_file = OpenOptions::new()
-            .create(true)
-            .truncate(true)
-            .write(true)
-            .open(order_data_path(config_dir))
-            .context("failed to open profile order data file for writing")?;
-
-        serde_json::to_writer(order_file, &self)
-            .context("failed to write profile order data to file")
+        update_metadata(config_dir, |metadata| {
+            let rearranged: HashSet<&str> = self.order.iter().map(String::as_str).collect();
+            let mut new_order = self.order.iter();
+            let mut order: Vec<String> = metadata.order.iter()
+                .filter_map(|id| if rearranged.contains(id.as_str()) {
+                    new_order.next().cloned()
+                } else {
+                    Some(id.clone())
+                })
+                .collect();
+            order.extend(new_order.cloned());
+            metadata.order = order;
+            Ok(())
+        })
     }
 }
 
@@ -0,0 +1,272 @@
+use std::path::Path;
+use once_cell::sync::OnceCell;
+use regex::Regex;
+use serde::{Serialize, Deserialize};
+use url::Url;
+use crate::stores::{read_versioned, write_versioned, Migration, VersionedStore, INITIAL_MIGRATIONS};
+use crate::ipc::notify_update_routing_rules;
+use crate::native_resp::{NativeResponseEvent, write_native_event};
+use crate::profiles::{ProfileEntry, ProfilesIniState};
+use crate::state::{AppContext, AppState};
+use crate::storage::routing_rules_data_path;
+
+// === URL ROUTING ===
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum UrlPattern {
+    // Glob matched against the host of the URL, e.g. "*.corp.example.com"
+    HostGlob { pattern: String },
+    // Regular expression matched against the full URL
+    Regex {
+        pattern: String,
+        // Compiled once per loaded rule set, missing if the pattern is invalid
+        #[serde(skip)]
+        compiled: OnceCell<Option<Regex>>
+    },
+    Scheme { scheme: String }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RoutingRule {
+    pub id: String,
+    pub pattern: UrlPattern,
+    pub profile_id: String,
+    #[serde(default = "default_rule_enabled")]
+    pub enabled: bool
+}
+
+fn default_rule_enabled() -> bool {
+    true
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct RoutingData {
+    pub rules: Vec<RoutingRule>,
+    // Profile that URLs no rule matches are opened in when the connector is the default browser
+    #[serde(default)]
+    pub fallback_profile_id: Option<String>
+}
+
+impl UrlPattern {
+    /// Make sure the pattern can actually be evaluated.
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            UrlPattern::HostGlob { pattern } if pattern.trim().is_empty() => Err("Host pattern cannot be empty.".to_owned()),
+            UrlPattern::Regex { pattern, .. } => Regex::new(pattern)
+                .map(|_| ())
+                .map_err(|e| format!("Invalid regular expression: {}", e)),
+            UrlPattern::Scheme { scheme } if scheme.trim().is_empty() => Err("Scheme cannot be empty.".to_owned()),
+            _ => Ok(())
+        }
+    }
+
+    fn matches(&self, url: &Url) -> bool {
+        match self {
+            UrlPattern::HostGlob { pattern } => match url.host_str() {
+                Some(host) => glob_matches(&pattern.trim().to_lowercase(), &host.to_lowercase()),
+                None => false
+            },
+            UrlPattern::Regex { pattern, compiled } => match compiled.get_or_init(|| compile_regex(pattern)) {
+                Some(regex) => regex.is_match(url.as_str()),
+                None => false
+            },
+            UrlPattern::Scheme { scheme } => url.scheme().eq_ignore_ascii_case(scheme.trim().trim_end_matches(':'))
+        }
+    }
+}
+
+fn compile_regex(pattern: &str) -> Option<Regex> {
+    match Regex::new(pattern) {
+        Ok(regex) => Some(regex),
+        Err(e) => {
+            log::warn!("Skipping routing rule with invalid regex {:?}: {:?}", pattern, e);
+            None
+        }
+    }
+}
+
+// Match text against a glob supporting '*' (any sequence) and '?' (any single character)
+fn glob_matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Position of the last '*' seen and the text position it was matched against
+    let mut backtrack: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = backtrack {
+            // Let the last '*' swallow one more character
+            p = star_p + 1;
+            t = star_t + 1;
+            backtrack = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+impl VersionedStore for RoutingData {
+    const DESCRIPTION: &'static str = "routing rules";
+    const MIGRATIONS: &'static [Migration] = INITIAL_MIGRATIONS;
+}
+
+impl RoutingData {
+    pub fn read(config_dir: &Path) -> RoutingData {
+        let routing_data: RoutingData = read_versioned(&routing_rules_data_path(config_dir));
+        // Compile the regular expressions up front instead of for every URL that is routed
+        for rule in &routing_data.rules {
+            if let UrlPattern::Regex { pattern, compiled } = &rule.pattern {
+                compiled.get_or_init(|| compile_regex(pattern));
+            }
+        }
+        routing_data
+    }
+
+    pub fn write(&self, config_dir: &Path) -> eyre::Result<()> {
+        write_versioned(&routing_rules_data_path(config_dir), self)
+    }
+
+    /// Find the profile the first matching rule sends the URL to.
+    pub fn route<'a>(&self, profiles: &'a ProfilesIniState, url: &str) -> Option<&'a ProfileEntry> {
+        let url = match Url::parse(url) {
+            Ok(u) => u,
+            Err(e) => {
+                log::trace!("Not routing unparseable URL {:?}: {:?}", url, e);
+                return None;
+            }
+        };
+
+        self.rules.iter()
+            .filter(|rule| rule.enabled && rule.pattern.matches(&url))
+            .find_map(|rule| {
+                let profile = profiles.profile_entries.iter().find(|p| p.id == rule.profile_id);
+                if profile.is_none() {
+                    log::warn!("Routing rule {} points to missing profile: {}", rule.id, rule.profile_id);
+                }
+                profile
+            })
+    }
+
+    /// Write the rules and let all running instances know about them.
+    pub fn write_and_notify(&self, context: &AppContext, profiles: &ProfilesIniState) -> eyre::Result<()> {
+        self.write(&context.state().config_dir)?;
+        notify_update_routing_rules(context, profiles);
+        Ok(())
+    }
+}
+
+pub fn native_notify_updated_routing_rules(app_state: &AppState) {
+    let routing_data = RoutingData::read(&app_state.config_dir);
+    write_native_event(NativeResponseEvent::RoutingRulesUpdated {
+        rules: routing_data.rules,
+        fallback_profile_id: routing_data.fallback_profile_id
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use crate::profiles::{ProfileEntry, ProfilesIniState};
+    use super::{glob_matches, RoutingData, RoutingRule, UrlPattern};
+
+    fn profile(id: &str) -> ProfileEntry {
+        ProfileEntry {
+            id: id.to_owned(),
+            name: id.to_owned(),
+            is_relative: true,
+            path: format!("Profiles/{}", id),
+            default: false,
+            avatar: None,
+            options: HashMap::new(),
+            root_id: None,
+            browser: None
+        }
+    }
+
+    fn rule(id: &str, host: &str, profile_id: &str, enabled: bool) -> RoutingRule {
+        RoutingRule {
+            id: id.to_owned(),
+            pattern: UrlPattern::HostGlob { pattern: host.to_owned() },
+            profile_id: profile_id.to_owned(),
+            enabled
+        }
+    }
+
+    fn route(routing_data: &RoutingData, url: &str) -> Option<String> {
+        let profiles = ProfilesIniState::from_entries(vec![profile("work"), profile("personal")]);
+        routing_data.route(&profiles, url).map(|p| p.id.clone())
+    }
+
+    #[test]
+    fn glob_matches_subdomains() {
+        assert!(glob_matches("*.example.com", "www.example.com"));
+        assert!(glob_matches("*.example.com", "a.b.example.com"));
+        assert!(!glob_matches("*.example.com", "example.com"));
+        assert!(!glob_matches("*.example.com", "www.example.org"));
+    }
+
+    #[test]
+    fn glob_matches_single_character() {
+        assert!(glob_matches("mail?.example.com", "mail1.example.com"));
+        assert!(!glob_matches("mail?.example.com", "mail.example.com"));
+        assert!(!glob_matches("mail?.example.com", "mail12.example.com"));
+    }
+
+    #[test]
+    fn glob_matches_trailing_star() {
+        assert!(glob_matches("intranet*", "intranet"));
+        assert!(glob_matches("intranet*", "intranet.corp.example.com"));
+        assert!(!glob_matches("intranet*", "www.intranet.com"));
+    }
+
+    #[test]
+    fn regex_rule_matches_full_url() {
+        let routing_data = RoutingData {
+            rules: vec![RoutingRule {
+                id: "1".to_owned(),
+                pattern: UrlPattern::Regex { pattern: "^https://example\\.com/work/".to_owned(), compiled: Default::default() },
+                profile_id: "work".to_owned(),
+                enabled: true
+            }],
+            fallback_profile_id: None
+        };
+        assert_eq!(route(&routing_data, "https://example.com/work/page"), Some("work".to_owned()));
+        assert_eq!(route(&routing_data, "https://example.com/home"), None);
+    }
+
+    #[test]
+    fn disabled_rules_are_skipped() {
+        let routing_data = RoutingData {
+            rules: vec![
+                rule("1", "*.example.com", "work", false),
+                rule("2", "*.example.com", "personal", true)
+            ],
+            fallback_profile_id: None
+        };
+        assert_eq!(route(&routing_data, "https://www.example.com/"), Some("personal".to_owned()));
+    }
+
+    #[test]
+    fn rules_of_missing_profiles_are_skipped() {
+        let routing_data = RoutingData {
+            rules: vec![
+                rule("1", "*.example.com", "deleted", true),
+                rule("2", "*.example.com", "work", true)
+            ],
+            fallback_profile_id: None
+        };
+        assert_eq!(route(&routing_data, "https://www.example.com/"), Some("work".to_owned()));
+        let routing_data = RoutingData {
+            rules: vec![rule("1", "*.example.com", "deleted", true)],
+            fallback_profile_id: None
+        };
+        assert_eq!(route(&routing_data, "https://www.example.com/"), None);
+    }
+}
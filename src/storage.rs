@@ -1,6 +1,134 @@
+use std::env;
 use std::path::{Path, PathBuf};
+use directories::ProjectDirs;
+use once_cell::sync::Lazy;
 use crate::{AppContext};
 
+// A file with this name next to the executable turns on portable mode
+const PORTABLE_MARKER: &str = "fps-portable";
+
+static PORTABLE_DIR: Lazy<Option<PathBuf>> = Lazy::new(|| {
+    let exe_dir = env::current_exe().ok()?.parent()?.to_path_buf();
+    Some(exe_dir).filter(|dir| dir.join(PORTABLE_MARKER).is_file())
+});
+
+/// The directory of the executable if we are running in portable mode, all state is kept there.
+pub fn portable_dir() -> Option<&'static Path> {
+    PORTABLE_DIR.as_deref()
+}
+
+/// Relative paths in the config are relative to the executable in portable mode.
+pub fn resolve_config_path(path: &Path) -> PathBuf {
+    match portable_dir() {
+        Some(dir) if path.is_relative() => dir.join(path),
+        _ => path.to_path_buf()
+    }
+}
+
+// Overrides for running isolated instances, e.g. for testing
+const CONFIG_DIR_ENV: &str = "FPS_CONFIG_DIR";
+const DATA_DIR_ENV: &str = "FPS_DATA_DIR";
+const PROFILE_ROOT_ENV: &str = "FPS_PROFILE_ROOT";
+const CONFIG_DIR_FLAG: &str = "--config-dir";
+const DATA_DIR_FLAG: &str = "--data-dir";
+const PROFILE_ROOT_FLAG: &str = "--profile-root";
+
+#[derive(Debug, Default)]
+pub struct StorageOverrides {
+    pub config_dir: Option<PathBuf>,
+    pub data_dir: Option<PathBuf>,
+    // Used instead of browser_profile_dir and the detected profile root
+    pub profile_root: Option<PathBuf>
+}
+
+impl StorageOverrides {
+    /// Read the overrides from the command line flags, falling back to the environment. The flags
+    /// are removed from the arguments.
+    pub fn from_args_and_env(args: &mut Vec<String>) -> Result<StorageOverrides, String> {
+        let mut overrides = StorageOverrides::default();
+        let mut remaining = Vec::with_capacity(args.len());
+        let mut iter = args.drain(..);
+        while let Some(arg) = iter.next() {
+            let (flag, value) = match arg.split_once('=') {
+                Some((flag, value)) => (flag.to_owned(), Some(value.to_owned())),
+                None => (arg.clone(), None)
+            };
+            let target = match flag.as_str() {
+                CONFIG_DIR_FLAG => &mut overrides.config_dir,
+                DATA_DIR_FLAG => &mut overrides.data_dir,
+                PROFILE_ROOT_FLAG => &mut overrides.profile_root,
+                _ => {
+                    remaining.push(arg);
+                    continue;
+                }
+            };
+            match value.or_else(|| iter.next()) {
+                Some(value) if !value.is_empty() => *target = Some(absolute_path(PathBuf::from(value))),
+                _ => return Err(format!("Missing directory after {}.", flag))
+            }
+        }
+        drop(iter);
+        *args = remaining;
+
+        let from_env = |name: &str| env::var_os(name)
+            .filter(|v| !v.is_empty())
+            .map(|v| absolute_path(PathBuf::from(v)));
+        overrides.config_dir = overrides.config_dir.or_else(|| from_env(CONFIG_DIR_ENV));
+        overrides.data_dir = overrides.data_dir.or_else(|| from_env(DATA_DIR_ENV));
+        overrides.profile_root = overrides.profile_root.or_else(|| from_env(PROFILE_ROOT_ENV));
+        Ok(overrides)
+    }
+
+    /// The config and data dirs to use, the platform's dirs unless they were overridden or we
+    /// are running in portable mode.
+    pub fn resolve_dirs(&self) -> (PathBuf, PathBuf) {
+        if let (Some(config_dir), Some(data_dir)) = (&self.config_dir, &self.data_dir) {
+            return (config_dir.clone(), data_dir.clone());
+        }
+        if let Some(portable_dir) = portable_dir() {
+            return (
+                self.config_dir.clone().unwrap_or_else(|| portable_dir.join("config")),
+                self.data_dir.clone().unwrap_or_else(|| portable_dir.join("data"))
+            );
+        }
+        let project_dirs = ProjectDirs::from("ax.nd",
+                                             "nulldev",
+                                             "FirefoxProfileSwitcher")
+            .expect("Could not initialize configuration (failed to find storage dir)!");
+        (
+            self.config_dir.clone().unwrap_or_else(|| project_dirs.preference_dir().to_path_buf()),
+            self.data_dir.clone().unwrap_or_else(|| project_dirs.data_local_dir().to_path_buf())
+        )
+    }
+}
+
+// The working dir may change when we launch browsers, so don't keep relative paths around
+fn absolute_path(path: PathBuf) -> PathBuf {
+    if path.is_absolute() {
+        return path;
+    }
+    env::current_dir()
+        .map(|cwd| cwd.join(&path))
+        .unwrap_or(path)
+}
+
+pub fn connector_config_path(config_dir: &Path) -> PathBuf {
+    config_dir.join("config.json")
+}
+
+pub fn metadata_data_path(config_dir: &Path) -> PathBuf {
+    config_dir.join("metadata.json")
+}
+
+pub fn metadata_lock_path(config_dir: &Path) -> PathBuf {
+    config_dir.join("metadata.lock")
+}
+
+pub fn ipc_secret_path(config_dir: &Path) -> PathBuf {
+    config_dir.join("ipc-secret")
+}
+
+// The stores below were replaced by the metadata store, they are only read to import them
 pub fn global_options_data_path(config_dir: &Path) -> PathBuf {
     config_dir.join("global-options.json")
 }
@@ -17,6 +145,22 @@ pub fn order_data_path(config_dir: &Path) -> PathBuf {
     config_dir.join("profile-order.json")
 }
 
+pub fn routing_rules_data_path(config_dir: &Path) -> PathBuf {
+    config_dir.join("routing-rules.json")
+}
+
+pub fn workspaces_data_path(config_dir: &Path) -> PathBuf {
+    config_dir.join("workspaces.json")
+}
+
+pub fn workspace_autostart_marker_path(data_dir: &Path) -> PathBuf {
+    data_dir.join("workspace-autostart")
+}
+
+pub fn launch_log_path(data_dir: &Path, profile_id: &str) -> PathBuf {
+    data_dir.join("launch-logs").join(format!("{}.log", profile_id))
+}
+
 pub fn custom_avatars_path(context: &AppContext) -> PathBuf {
-    context.state.data_dir.join("avatars")
+    context.state().data_dir.join("avatars")
 }
\ No newline at end of file
@@ -0,0 +1,260 @@
+use std::fs;
+use std::fs::OpenOptions;
+use std::path::{Path, PathBuf};
+use eyre::{eyre, Context};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+use crate::diagnostics::{check_store_writable, quarantine_store, read_store};
+use crate::metadata::{import_legacy_metadata, Metadata};
+use crate::routing::RoutingData;
+use crate::storage::{metadata_data_path, routing_rules_data_path, workspaces_data_path};
+use crate::workspaces::WorkspaceData;
+
+// === VERSIONED STORES ===
+
+const SCHEMA_VERSION_KEY: &str = "schemaVersion";
+
+/// Upgrades the contents of a store by one schema version.
+pub type Migration = fn(&mut Map<String, Value>) -> eyre::Result<()>;
+
+/// A JSON object store that records its schema version. Files written before stores were versioned
+/// are at version 0.
+pub trait VersionedStore: Serialize + DeserializeOwned + Default {
+    // Used in logs and errors, e.g. "profile order data"
+    const DESCRIPTION: &'static str;
+    // MIGRATIONS[n] upgrades version n to n + 1, so the current version is the number of migrations
+    const MIGRATIONS: &'static [Migration];
+
+    fn schema_version() -> u64 {
+        Self::MIGRATIONS.len() as u64
+    }
+}
+
+// Stores had no version before, their format did not change
+fn migrate_unversioned(_: &mut Map<String, Value>) -> eyre::Result<()> {
+    Ok(())
+}
+pub const INITIAL_MIGRATIONS: &[Migration] = &[migrate_unversioned];
+
+fn stored_version(object: &Map<String, Value>) -> eyre::Result<u64> {
+    match object.get(SCHEMA_VERSION_KEY) {
+        None => Ok(0),
+        Some(v) => v.as_u64().ok_or_else(|| eyre!("{} is not a number: {}", SCHEMA_VERSION_KEY, v))
+    }
+}
+
+// Bring the contents up to the current version, returns the version they were at
+fn migrate<T: VersionedStore>(object: &mut Map<String, Value>) -> eyre::Result<u64> {
+    let version = stored_version(object)?;
+    if version > T::schema_version() {
+        // Try our best with data from a newer connector, we won't write it back
+        log::warn!("{} is at schema version {} which is newer than ours ({})", T::DESCRIPTION, version, T::schema_version());
+        return Ok(version);
+    }
+    for (from, migration) in T::MIGRATIONS.iter().enumerate().skip(version as usize) {
+        log::info!("Migrating {} from schema version {} to {}", T::DESCRIPTION, from, from + 1);
+        migration(object).with_context(|| format!("failed to migrate {} from schema version {}", T::DESCRIPTION, from))?;
+    }
+    Ok(version)
+}
+
+fn decode<T: VersionedStore>(value: Value) -> eyre::Result<(T, u64)> {
+    let mut object = match value {
+        Value::Object(o) => o,
+        other => return Err(eyre!("{} is not a JSON object: {}", T::DESCRIPTION, other))
+    };
+    let version = migrate::<T>(&mut object)?;
+    object.remove(SCHEMA_VERSION_KEY);
+    let data = serde_json::from_value(Value::Object(object))
+        .with_context(|| format!("{} is incorrectly formatted", T::DESCRIPTION))?;
+    Ok((data, version))
+}
+
+/// Read a store, migrating its contents to the current schema version in memory.
+pub fn read_versioned<T: VersionedStore>(path: &Path) -> T {
+    if !path.exists() {
+        return T::default();
+    }
+    let value: Value = read_store(path, T::DESCRIPTION);
+    if value.is_null() {
+        // The file was unreadable and has already been dealt with
+        return T::default();
+    }
+    match decode::<T>(value) {
+        Ok((data, _)) => data,
+        Err(e) => {
+            log::error!("Failed to read {}: {:?}", T::DESCRIPTION, e);
+            quarantine_store(path, format!("{:#}", e));
+            T::default()
+        }
+    }
+}
+
+/// Write a store at the current schema version. Stores written by a newer connector are never
+/// replaced, we would lose whatever it added.
+pub fn write_versioned<T: VersionedStore>(path: &Path, data: &T) -> eyre::Result<()> {
+    check_store_writable(path)?;
+
+    let on_disk_version = fs::read(path).ok()
+        .and_then(|raw| serde_json::from_slice::<Map<String, Value>>(&raw).ok())
+        .and_then(|object| stored_version(&object).ok());
+    if let Some(version) = on_disk_version.filter(|v| *v > T::schema_version()) {
+        return Err(eyre!("{} was written by a newer version of the connector (schema version {}, ours is {}), refusing to overwrite it",
+                         T::DESCRIPTION, version, T::schema_version()));
+    }
+
+    let mut object = match serde_json::to_value(data).context("failed to serialize store")? {
+        Value::Object(o) => o,
+        _ => return Err(eyre!("{} must be a JSON object", T::DESCRIPTION))
+    };
+    object.insert(SCHEMA_VERSION_KEY.to_owned(), Value::from(T::schema_version()));
+
+    // Write to a temporary file first so a crash never leaves a half written store behind
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(true)
+        .write(true)
+        .open(&tmp_path)
+        .with_context(|| format!("failed to open {} file for writing", T::DESCRIPTION))?;
+    serde_json::to_writer(&file, &object)
+        .with_context(|| format!("failed to write {} to file", T::DESCRIPTION))?;
+    file.sync_all()
+        .with_context(|| format!("failed to flush {} file", T::DESCRIPTION))?;
+    fs::rename(&tmp_path, path)
+        .with_context(|| format!("failed to replace {} file", T::DESCRIPTION))
+}
+
+// Upgrade the file on disk, keeping a copy of the old version
+fn migrate_store_file<T: VersionedStore>(path: &Path) {
+    let raw = match fs::read(path) {
+        Ok(r) => r,
+        Err(_) => return
+    };
+    let mut object: Map<String, Value> = match serde_json::from_slice(&raw) {
+        Ok(o) => o,
+        // Reading the store will quarantine it
+        Err(_) => return
+    };
+    let version = match stored_version(&object) {
+        Ok(v) if v < T::schema_version() => v,
+        _ => return
+    };
+
+    let backup_path = backup_path(path, version);
+    if let Err(e) = fs::write(&backup_path, &raw) {
+        log::error!("Not migrating {} as it could not be backed up to {:?}: {:?}", T::DESCRIPTION, backup_path, e);
+        return;
+    }
+
+    let result = migrate::<T>(&mut object)
+        .and_then(|_| {
+            object.remove(SCHEMA_VERSION_KEY);
+            serde_json::from_value::<T>(Value::Object(object))
+                .with_context(|| format!("migrated {} is incorrectly formatted", T::DESCRIPTION))
+        })
+        .and_then(|data| write_versioned(path, &data));
+    match result {
+        Ok(()) => log::info!("Migrated {} from schema version {}, backup is at {:?}", T::DESCRIPTION, version, backup_path),
+        Err(e) => log::error!("Failed to migrate {}, backup is at {:?}: {:?}", T::DESCRIPTION, backup_path, e)
+    }
+}
+
+fn backup_path(path: &Path, version: u64) -> PathBuf {
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(format!(".v{}.bak", version));
+    PathBuf::from(backup)
+}
+
+/// Bring every store up to the current schema version.
+pub fn migrate_stores(config_dir: &Path) {
+    // The legacy stores are migrated in memory while importing them
+    import_legacy_metadata(config_dir);
+    migrate_store_file::<Metadata>(&metadata_data_path(config_dir));
+    migrate_store_file::<RoutingData>(&routing_rules_data_path(config_dir));
+    migrate_store_file::<WorkspaceData>(&workspaces_data_path(config_dir));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use serde::{Serialize, Deserialize};
+    use serde_json::{Map, Value};
+    use super::{migrate_store_file, read_versioned, write_versioned, Migration, VersionedStore, INITIAL_MIGRATIONS};
+
+    #[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+    struct TestStore {
+        #[serde(default)]
+        title: String
+    }
+
+    // Version 1 renamed "name" to "title"
+    fn migrate_rename_title(object: &mut Map<String, Value>) -> eyre::Result<()> {
+        if let Some(name) = object.remove("name") {
+            object.insert("title".to_owned(), name);
+        }
+        Ok(())
+    }
+    const TEST_MIGRATIONS: &[Migration] = &[INITIAL_MIGRATIONS[0], migrate_rename_title];
+
+    impl VersionedStore for TestStore {
+        const DESCRIPTION: &'static str = "test store";
+        const MIGRATIONS: &'static [Migration] = TEST_MIGRATIONS;
+    }
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("fps-test-stores-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn read_json(path: &Path) -> Value {
+        serde_json::from_slice(&fs::read(path).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn unversioned_store_is_migrated_and_backed_up() {
+        let dir = test_dir("unversioned");
+        let path = dir.join("store.json");
+        fs::write(&path, br#"{"name":"test"}"#).unwrap();
+
+        migrate_store_file::<TestStore>(&path);
+
+        assert_eq!(read_json(&path), serde_json::json!({ "title": "test", "schemaVersion": 2 }));
+        assert_eq!(fs::read(dir.join("store.json.v0.bak")).unwrap(), br#"{"name":"test"}"#);
+        assert_eq!(read_versioned::<TestStore>(&path), TestStore { title: "test".to_owned() });
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn current_store_is_not_backed_up() {
+        let dir = test_dir("current");
+        let path = dir.join("store.json");
+        fs::write(&path, br#"{"title":"test","schemaVersion":2}"#).unwrap();
+
+        migrate_store_file::<TestStore>(&path);
+
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn newer_store_is_read_but_not_written() {
+        let dir = test_dir("newer");
+        let path = dir.join("store.json");
+        let newer = br#"{"title":"test","added":true,"schemaVersion":3}"#;
+        fs::write(&path, newer).unwrap();
+
+        assert_eq!(read_versioned::<TestStore>(&path), TestStore { title: "test".to_owned() });
+        migrate_store_file::<TestStore>(&path);
+        assert!(write_versioned(&path, &TestStore { title: "changed".to_owned() }).is_err());
+
+        assert_eq!(fs::read(&path).unwrap(), newer);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
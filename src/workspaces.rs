@@ -0,0 +1,168 @@
+use std::fs;
+use std::path::Path;
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use crate::cmd::execute_cmd_for_message;
+use crate::stores::{read_versioned, write_versioned, Migration, VersionedStore, INITIAL_MIGRATIONS};
+use crate::ipc::notify_update_workspaces;
+use crate::native_req::{NativeMessage, NativeMessageLaunchProfile};
+use crate::native_resp::{NativeResponse, NativeResponseEvent, write_native_event};
+use crate::options::read_global_options;
+use crate::profiles::{read_profiles, ProfilesIniState};
+use crate::state::{AppContext, AppState};
+use crate::storage::{workspace_autostart_marker_path, workspaces_data_path};
+
+// Global option holding the ID of the workspace to launch when the browser starts
+const AUTO_LAUNCH_WORKSPACE_OPTION: &str = "autoLaunchWorkspace";
+
+// === WORKSPACES ===
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WorkspaceMember {
+    pub profile_id: String,
+    #[serde(default)]
+    pub urls: Vec<String>,
+    // Open the URLs in a new window instead of new tabs
+    #[serde(default)]
+    pub new_window: bool
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Workspace {
+    pub id: String,
+    pub name: String,
+    pub members: Vec<WorkspaceMember>
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct WorkspaceData {
+    pub workspaces: Vec<Workspace>
+}
+
+impl VersionedStore for WorkspaceData {
+    const DESCRIPTION: &'static str = "workspaces";
+    const MIGRATIONS: &'static [Migration] = INITIAL_MIGRATIONS;
+}
+
+impl WorkspaceData {
+    pub fn read(config_dir: &Path) -> WorkspaceData {
+        read_versioned(&workspaces_data_path(config_dir))
+    }
+
+    pub fn write(&self, config_dir: &Path) -> eyre::Result<()> {
+        write_versioned(&workspaces_data_path(config_dir), self)
+    }
+
+    /// Write the workspaces and let all running instances know about them.
+    pub fn write_and_notify(&self, context: &AppContext, profiles: &ProfilesIniState) -> eyre::Result<()> {
+        self.write(&context.state().config_dir)?;
+        notify_update_workspaces(context, profiles);
+        Ok(())
+    }
+
+    pub fn find(&self, name_or_id: &str) -> Option<&Workspace> {
+        self.workspaces.iter()
+            .find(|w| w.id == name_or_id)
+            .or_else(|| self.workspaces.iter().find(|w| w.name.trim().eq_ignore_ascii_case(name_or_id.trim())))
+    }
+}
+
+/// Focus or launch every profile in the workspace and open its URLs. Returns the profiles that
+/// failed to launch along with the reason.
+pub fn launch_workspace(context: &AppContext, workspace: &Workspace) -> Vec<(String, NativeResponse)> {
+    log::trace!("Launching workspace: {}", workspace.id);
+
+    let mut failures = Vec::new();
+    for member in &workspace.members {
+        // The first launch waits for the browser to start, so the remaining URLs reach the running instance
+        let urls: Vec<Option<String>> = if member.urls.is_empty() {
+            vec![None]
+        } else {
+            member.urls.iter().cloned().map(Some).collect()
+        };
+        for (i, url) in urls.into_iter().enumerate() {
+            let response = execute_cmd_for_message(context, NativeMessage::LaunchProfile(NativeMessageLaunchProfile {
+                profile_id: member.profile_id.clone(),
+                url,
+                // Only the first URL gets a new window, the rest are opened as tabs in it
+                new_window: member.new_window && i == 0
+            }));
+            if let NativeResponse::Error { .. } = response {
+                log::error!("Failed to launch profile {} of workspace {}: {:?}", member.profile_id, workspace.id, response);
+                failures.push((member.profile_id.clone(), response));
+                break;
+            }
+        }
+    }
+    failures
+}
+
+// Launch the configured workspace once per browser session, from the default profile only
+pub fn autostart_workspace(context: &AppContext) {
+    let global_options = read_global_options(&context.state().config_dir);
+    let workspace_id = match global_options.get(AUTO_LAUNCH_WORKSPACE_OPTION) {
+        Some(Value::String(id)) => id.clone(),
+        _ => return
+    };
+
+    let profiles = match read_profiles(&context.state().config, &context.state().config_dir) {
+        Ok(p) => p,
+        Err(e) => {
+            log::error!("Failed to read profiles to auto-launch workspace: {:?}", e);
+            return;
+        }
+    };
+    let is_default_profile = profiles.profile_entries.iter()
+        .any(|p| p.default && Some(&p.id) == context.state().cur_profile_id.as_ref());
+    if !is_default_profile {
+        return;
+    }
+
+    // The extension may reconnect to us during a browser session, only launch the first time
+    if !claim_autostart(&context.state()) {
+        log::trace!("Workspace was already auto-launched in this browser session.");
+        return;
+    }
+
+    match WorkspaceData::read(&context.state().config_dir).find(&workspace_id) {
+        Some(workspace) => {
+            launch_workspace(context, workspace);
+        }
+        None => log::warn!("Workspace to auto-launch does not exist: {}", workspace_id)
+    }
+}
+
+// Remember which browser process we auto-launched the workspace for
+fn claim_autostart(app_state: &AppState) -> bool {
+    let session = match browser_session_id() {
+        Some(s) => s,
+        // We can't tell browser sessions apart, so launch every time
+        None => return true
+    };
+
+    let marker_path = workspace_autostart_marker_path(&app_state.data_dir);
+    if fs::read_to_string(&marker_path).ok().as_deref() == Some(session.as_str()) {
+        return false;
+    }
+    if let Err(e) = fs::write(&marker_path, &session) {
+        log::warn!("Failed to write workspace auto-launch marker: {:?}", e);
+    }
+    true
+}
+
+fn browser_session_id() -> Option<String> {
+    cfg_if::cfg_if! {
+        if #[cfg(target_family = "unix")] {
+            // The browser is our parent process
+            Some(std::os::unix::process::parent_id().to_string())
+        } else {
+            None
+        }
+    }
+}
+
+pub fn native_notify_updated_workspaces(app_state: &AppState) {
+    write_native_event(NativeResponseEvent::WorkspacesUpdated {
+        workspaces: WorkspaceData::read(&app_state.config_dir).workspaces
+    });
+}